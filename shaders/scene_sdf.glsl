@@ -0,0 +1,1032 @@
+// The scene's distance function and the buffers it reads, shared by the shaders that evaluate the scene. Declares
+// the SceneInfo uniform and scene buffers in set 0, leaving bindings 1 and 2 for the including shader's own outputs
+
+const uint SHAPE_TYPE_NONE = 0;
+const uint SHAPE_TYPE_SPHERE = 1;
+const uint SHAPE_TYPE_WOBBLY_SPHERE = 2;
+const uint SHAPE_TYPE_MANDELBULB = 3;
+const uint SHAPE_TYPE_BOX = 4;
+const uint SHAPE_TYPE_ROUNDED_BOX = 5;
+const uint SHAPE_TYPE_TORUS = 6;
+const uint SHAPE_TYPE_CAPSULE = 7;
+const uint SHAPE_TYPE_CYLINDER = 8;
+const uint SHAPE_TYPE_CONE = 9;
+const uint SHAPE_TYPE_PLANE = 10;
+const uint SHAPE_TYPE_ELLIPSOID = 11;
+const uint SHAPE_TYPE_HEX_PRISM = 12;
+const uint SHAPE_TYPE_MANDELBOX = 13;
+const uint SHAPE_TYPE_MENGER_SPONGE = 14;
+const uint SHAPE_TYPE_SIERPINSKI_TETRAHEDRON = 15;
+const uint SHAPE_TYPE_QUATERNION_JULIA = 16;
+const uint SHAPE_TYPE_KIFS = 17;
+const uint SHAPE_TYPE_HYBRID = 18;
+const uint SHAPE_TYPE_GYROID = 19;
+const uint SHAPE_TYPE_SCHWARZ_P = 20;
+const uint SHAPE_TYPE_SCHWARZ_D = 21;
+const uint SHAPE_TYPE_HEIGHTMAP = 22;
+const uint SHAPE_TYPE_MESH = 23;
+const uint SHAPE_TYPE_BAKED = 24;
+
+const float PI = 3.14159265;
+
+// Caps the iterations a fractal shape runs per distance estimate, matching the limit checked on the CPU
+const uint MAX_FRACTAL_ITERATIONS = 256;
+
+
+// The most transforms applied to a shape, i.e. its own and those of its parents
+const uint MAX_TRANSFORM_DEPTH = 8;
+
+struct Shape {
+	vec3 position;
+	uint shape_type;
+	vec4 rotation; // Unit quaternion (x, y, z, w)
+	vec3 scale;
+	int parent; // Index of the shape this shape's transform is relative to, or -1
+	vec3 size;
+	uint domain_op; // Folds space in the shape's local space, so a shape or group is repeated or mirrored
+	vec3 albedo;
+	int palette; // Index of the palette the shape is coloured by instead of its albedo, or -1
+	vec4 params[2]; // Parameters specific to shape_type, e.g. the corner radius of a rounded box or a Mandelbulb's (power, iterations, bailout)
+	vec4 domain_params[2]; // Parameters specific to domain_op
+	uint first_modifier; // The range of the modifier buffer the shape is deformed by, in order
+	uint num_modifiers;
+	int material; // Index of the material the shape is shaded with, or -1. The albedo is already the material's
+};
+
+const uint DOMAIN_OP_NONE = 0;
+const uint DOMAIN_OP_REPEAT = 1; // domain_params[0].xyz is the spacing along each axis, 0 for no repetition
+const uint DOMAIN_OP_REPEAT_LIMITED = 2; // As DOMAIN_OP_REPEAT, with domain_params[1].xyz copies either side of the original
+const uint DOMAIN_OP_MIRROR = 3; // domain_params[0].xyz is 1 for the axes to mirror, 0 otherwise, and domain_params[1].xyz the offset of the copies
+const uint DOMAIN_OP_POLAR = 4; // domain_params[0].xy is (number of copies around the y axis, distance of the copies from the axis)
+
+// The shapes in a scene are combined by a postfix program of these instructions, run on a stack of (colour, distance)
+const uint CSG_OP_PUSH_SHAPE = 0;
+const uint CSG_OP_COMBINE = 1; // Pops two values and pushes them combined by blend_mode
+const uint CSG_STACK_SIZE = 16;
+
+struct CsgInstruction {
+	uint op;
+	uint shape_index; // Used by CSG_OP_PUSH_SHAPE
+	uint blend_mode; // Used by CSG_OP_COMBINE
+	float blend_strength; // Used by CSG_OP_COMBINE
+};
+
+// Fractal shapes output (iterations, smooth iterations, point trap, plane trap) per hit, which palettes map to colours
+const uint PALETTE_SOURCE_ITERATIONS = 0;
+const uint PALETTE_SOURCE_SMOOTH_ITERATIONS = 1;
+const uint PALETTE_SOURCE_POINT_TRAP = 2;
+const uint PALETTE_SOURCE_PLANE_TRAP = 3;
+const uint MAX_PALETTE_STOPS = 8;
+
+struct Palette {
+	vec4 stops[MAX_PALETTE_STOPS]; // Colour in rgb, position along the gradient in a, in order of position
+	uint source;
+	uint num_stops;
+	float scale; // Applied to the source value before the offset
+	float offset;
+	uint repeat; // Whether values outside 0 - 1 wrap around rather than being clamped
+};
+
+// Kifs shapes run a range of these operations on each point every iteration
+const uint FRACTAL_OP_BOX_FOLD = 0; // params.x is the fold limit
+const uint FRACTAL_OP_SPHERE_FOLD = 1; // params.xy is (min radius, fixed radius)
+const uint FRACTAL_OP_PLANE_REFLECTION = 2; // params.xyz is the unit normal, params.w the distance from the centre
+const uint FRACTAL_OP_ROTATION = 3; // params is a unit quaternion
+const uint FRACTAL_OP_SCALE = 4; // params.x is the scale
+const uint FRACTAL_OP_OFFSET = 5; // params.xyz is the offset
+// Hybrid shapes run one of these per iteration, cycling through their range
+const uint FRACTAL_OP_MANDELBULB = 6; // params.x is the power
+const uint FRACTAL_OP_MANDELBOX = 7; // params.xyz is (scale, min radius, fixed radius)
+
+struct FractalOp {
+	vec4 params;
+	uint op;
+};
+
+// Twists and bends deform the point before the shape is evaluated, the others deform the distance after
+const uint MODIFIER_TWIST = 0; // params.x is the rate in radians per unit along y
+const uint MODIFIER_BEND = 1; // params.x is the rate in radians per unit along x
+const uint MODIFIER_SINE_DISPLACEMENT = 2; // params.xy is (amplitude, frequency)
+const uint MODIFIER_NOISE_DISPLACEMENT = 3; // params.xy is (amplitude, frequency)
+const uint MODIFIER_ROUND = 4; // params.x is the radius
+const uint MODIFIER_ONION = 5; // params.x is the thickness
+const uint MODIFIER_COLOUR_NOISE = 6; // Mixes colour into the shape's colour, params.xy is (strength, frequency)
+
+struct Modifier {
+	vec4 params; // w is the factor the distance is scaled by to stay a lower bound despite the deformation
+	vec3 colour;
+	uint noise_type; // For the noise modifiers, which noise to sample and how many octaves to sum
+	uint op;
+	uint octaves;
+};
+
+const uint NOISE_VALUE = 0;
+const uint NOISE_GRADIENT = 1;
+const uint NOISE_SIMPLEX = 2;
+
+const uint MAX_NOISE_OCTAVES = 8;
+
+// ============================
+
+// Descriptor 0 in set 0
+layout(set = 0, binding = 0) uniform SceneInfo {
+	vec3 camera_pos;
+	vec3 look_at;
+	float canvas_dist;
+	uint num_shapes;
+	uint noise_seed;
+	float noise_lacunarity; // Each octave of FBM is noise_lacunarity times the frequency and noise_gain times the amplitude of the last
+	float noise_gain;
+	uint num_instructions;
+	uint num_lights;
+	uint shadows; // 0 turns every light's shadows off
+	uint ao_samples; // 0 turns ambient occlusion off
+	float ao_step;
+	float ao_strength;
+	uint output_mode;
+} scene;
+
+// Descriptor 3 in set 0 - Only the first scene.num_shapes shapes are valid
+layout(set = 0, binding = 3) readonly buffer Shapes {
+	Shape shapes[];
+} shape_buffer;
+
+// Descriptor 4 in set 0 - Only the first scene.num_instructions instructions are valid
+layout(set = 0, binding = 4) readonly buffer CsgInstructions {
+	CsgInstruction instructions[];
+} instruction_buffer;
+
+// Descriptor 5 in set 0 - Indexed by Shape::palette
+layout(set = 0, binding = 5) readonly buffer Palettes {
+	Palette palettes[];
+} palette_buffer;
+
+// Descriptor 6 in set 0 - Kifs and hybrid shapes use the range given by Shape::params[1].xy
+layout(set = 0, binding = 6) readonly buffer FractalOps {
+	FractalOp ops[];
+} fractal_op_buffer;
+
+// Descriptor 7 in set 0 - Indexed by Shape::first_modifier
+layout(set = 0, binding = 7) readonly buffer Modifiers {
+	Modifier modifiers[];
+} modifier_buffer;
+
+// Descriptor 8 in set 0 - One heightmap per layer, indexed by a heightmap shape's params[0].x. Heights are from 0 to 1
+layout(set = 0, binding = 8) uniform sampler2DArray heightmaps;
+
+// Descriptor 9 in set 0 - Signed distance grids stacked along z, in units of their cells. A volume shape's params
+// give where its grid is
+layout(set = 0, binding = 9) uniform sampler3D volumes;
+
+// ============================
+
+// Polynomial smooth min (mix factor): https://iquilezles.org/articles/smin/
+vec2 smooth_min(float a, float b, float k) {
+	float h = max(k - abs(a - b), 0.0) / k;
+	float m = h * h * 0.5;
+	float s = m * k * (1.0 / 2.0);
+
+	return (a < b) ? vec2(a - s, m) : vec2(b - s, 1.0 - m);
+}
+
+const uint BLEND_MODE_NONE = 0;
+const uint BLEND_MODE_INTERSECTION = 1;
+const uint BLEND_MODE_DIFFERENCE = 2;
+const uint BLEND_MODE_SMOOTH = 3;
+
+// Returns the combined distance, and how much of the second operand's colour (or other surface properties) to mix in
+vec2 blend(float dist0, float dist1, uint blend_mode, float blend_strength) {
+	if(blend_mode == BLEND_MODE_INTERSECTION) {
+		return (dist1 > dist0) ? vec2(dist1, 1.0) : vec2(dist0, 0.0);
+	} else if(blend_mode == BLEND_MODE_DIFFERENCE) {
+		return (-dist1 > dist0) ? vec2(-dist1, 1.0) : vec2(dist0, 0.0);
+	} else if(blend_mode == BLEND_MODE_SMOOTH) {
+		return smooth_min(dist0, dist1, blend_strength);//vec2(mix(dist0, dist1, 0.5), 0.5);
+	} else { // Assume BLEND_MODE_NONE
+		return (dist1 < dist0) ? vec2(dist1, 1.0) : vec2(dist0, 0.0);
+	}
+}
+
+vec4 combine(float dist0, vec3 col0, float dist1, vec3 col1, uint blend_mode, float blend_strength) {
+	vec2 dist_and_mix_factor = blend(dist0, dist1, blend_mode, blend_strength);
+	return vec4(mix(col0, col1, dist_and_mix_factor.y), dist_and_mix_factor.x);
+}
+
+// ============================
+
+// Rotates v by the unit quaternion q
+vec3 rotate(vec3 v, vec4 q) {
+	return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+
+// Folds p into the cell of the shape's domain operator that holds the original shape
+// https://iquilezles.org/articles/sdfrepetition/
+vec3 apply_domain_op(vec3 p, Shape shape) {
+	if(shape.domain_op == DOMAIN_OP_REPEAT || shape.domain_op == DOMAIN_OP_REPEAT_LIMITED) {
+		vec3 spacing = shape.domain_params[0].xyz;
+		// Axes with no spacing aren't repeated
+		vec3 safe_spacing = max(spacing, vec3(1e-6));
+		vec3 cell = round(p / safe_spacing);
+		if(shape.domain_op == DOMAIN_OP_REPEAT_LIMITED) {
+			cell = clamp(cell, -shape.domain_params[1].xyz, shape.domain_params[1].xyz);
+		}
+		p -= step(1e-6, spacing) * spacing * cell;
+	} else if(shape.domain_op == DOMAIN_OP_MIRROR) {
+		p = mix(p, abs(p) - shape.domain_params[1].xyz, shape.domain_params[0].xyz);
+	} else if(shape.domain_op == DOMAIN_OP_POLAR) {
+		float sector = 2.0 * PI / shape.domain_params[0].x;
+		float angle = atan(p.z, p.x) + sector * 0.5;
+		angle = mod(angle, sector) - sector * 0.5;
+		p.xz = length(p.xz) * vec2(cos(angle), sin(angle));
+		p.x -= shape.domain_params[0].y;
+	}
+
+	return p;
+}
+
+// Transforms p into the local space of the shape at shape_index, applying the inverses of the transforms of its parents first,
+// each followed by the domain operator of that shape or group.
+// Returns the local point, and in w the factor that local distances need multiplying by to be world distances
+vec4 to_local_space(vec3 p, uint shape_index) {
+	uint chain[MAX_TRANSFORM_DEPTH];
+	uint chain_len = 0;
+	int index = int(shape_index);
+	while(index >= 0 && chain_len < MAX_TRANSFORM_DEPTH) {
+		chain[chain_len] = uint(index);
+		chain_len++;
+		index = shape_buffer.shapes[index].parent;
+	}
+
+	float dist_scale = 1.0;
+	for(uint i = chain_len; i > 0; i--) {
+		Shape transform = shape_buffer.shapes[chain[i - 1]];
+		p = rotate(p - transform.position, vec4(-transform.rotation.xyz, transform.rotation.w)) / transform.scale;
+		p = apply_domain_op(p, transform);
+		// Using the smallest scale factor keeps the distance a lower bound when the scale isn't uniform
+		dist_scale *= min(transform.scale.x, min(transform.scale.y, transform.scale.z));
+	}
+
+	return vec4(p, dist_scale);
+}
+
+// ============================
+
+float sdf_sphere(vec3 origin, vec3 sph_centre, float sph_radius) {
+	return distance(origin, sph_centre) - sph_radius;
+}
+
+float sdf_wobbly_sphere(vec3 origin, vec3 sph_centre, float sph_radius) {
+	return sdf_sphere(origin, sph_centre, sph_radius) + (sin(5 * origin.x) + sin(5 * origin.y) + sin(5 * origin.z) * 0.025);
+}
+
+// I do not understand this at all
+// http://blog.hvidtfeldts.net/index.php/2011/09/distance-estimated-3d-fractals-v-the-mandelbulb-different-de-approximations/
+
+// One iteration of the Mandelbulb formula, z = z^power + c, updating dr, the running derivative
+void mandelbulb_step(inout vec3 z, inout float dr, vec3 c, float power) {
+	float r = length(z);
+
+	// Convert to polar coords
+	float theta = acos(z.z / r);
+	float phi = atan(z.y, z.x);
+	dr = pow(r, power - 1.0) * power * dr + 1.0;
+
+	// Scale and rotate the point
+	float zr = pow(r, power);
+	theta = theta * power;
+	phi = phi * power;
+
+	// Convert back to cartesian coords
+	z = zr * vec3(sin(theta) * cos(phi), sin(phi) * sin(theta), cos(theta));
+	z += c;
+}
+
+// One iteration of the Mandelbox formula, a box fold with a limit of 1 then a sphere fold, then z = z * scale + c
+// https://digitalfreepen.com/2017/06/20/range-rendering-mandelbox.html
+void mandelbox_step(inout vec3 z, inout float dr, vec3 c, float scale, float min_radius, float fixed_radius) {
+	// Box fold
+	z = clamp(z, -1.0, 1.0) * 2.0 - z;
+
+	// Sphere fold
+	float min_radius2 = min_radius * min_radius;
+	float fixed_radius2 = fixed_radius * fixed_radius;
+	float r2 = dot(z, z);
+	if(r2 < min_radius2) {
+		z *= fixed_radius2 / min_radius2;
+		dr *= fixed_radius2 / min_radius2;
+	} else if(r2 < fixed_radius2) {
+		z *= fixed_radius2 / r2;
+		dr *= fixed_radius2 / r2;
+	}
+
+	z = z * scale + c;
+	dr = dr * abs(scale) + 1.0;
+}
+
+// aux is set to the data palettes can colour the hit by, see PALETTE_SOURCE_*
+float sdf_mandelbulb(vec3 origin, float power, uint iters, float bailout, out vec4 aux) {
+	iters = min(iters, MAX_FRACTAL_ITERATIONS);
+
+	vec3 z = origin;
+	float dr = 1.0;
+	float r = 0.0;
+	uint i = 0;
+	float point_trap = 1e20;
+	float plane_trap = 1e20;
+	for(; i < iters; i++) {
+		r = length(z);
+		if(r > bailout) {
+			break;
+		}
+		point_trap = min(point_trap, r);
+		plane_trap = min(plane_trap, min(abs(z.x), min(abs(z.y), abs(z.z))));
+
+		mandelbulb_step(z, dr, origin, power);
+	}
+
+	// Smooths out the bands between iteration counts, using how far past the bailout the point escaped
+	float smooth_iters = float(i);
+	if(i < iters) {
+		smooth_iters += 1.0 - log(max(log(r) / log(bailout), 1.0)) / log(power);
+	}
+	aux = vec4(float(i) / float(iters), clamp(smooth_iters / float(iters), 0.0, 1.0), point_trap, plane_trap);
+
+	float dist = 0.5 * log(r) * r / dr;
+	return dist;
+}
+
+// Most of the primitives below are from https://iquilezles.org/articles/distfunctions/
+// Shapes with an axis are aligned with the y axis, and all are centred on the origin
+
+float sdf_box(vec3 origin, vec3 half_extents) {
+	vec3 q = abs(origin) - half_extents;
+	return length(max(q, 0.0)) + min(max(q.x, max(q.y, q.z)), 0.0);
+}
+
+// half_extents includes the rounded corners
+float sdf_rounded_box(vec3 origin, vec3 half_extents, float radius) {
+	return sdf_box(origin, half_extents - radius) - radius;
+}
+
+// Lies in the xz plane
+float sdf_torus(vec3 origin, float ring_radius, float tube_radius) {
+	vec2 q = vec2(length(origin.xz) - ring_radius, origin.y);
+	return length(q) - tube_radius;
+}
+
+// half_height is that of the line segment the capsule surrounds, excluding the end caps
+float sdf_capsule(vec3 origin, float radius, float half_height) {
+	vec3 p = origin;
+	p.y -= clamp(p.y, -half_height, half_height);
+	return length(p) - radius;
+}
+
+float sdf_cylinder(vec3 origin, float radius, float half_height) {
+	vec2 d = abs(vec2(length(origin.xz), origin.y)) - vec2(radius, half_height);
+	return min(max(d.x, d.y), 0.0) + length(max(d, 0.0));
+}
+
+// A capped cone, with the base radius at -half_height and the top radius at +half_height
+float sdf_cone(vec3 origin, float base_radius, float top_radius, float half_height) {
+	vec2 q = vec2(length(origin.xz), origin.y);
+	vec2 k1 = vec2(top_radius, half_height);
+	vec2 k2 = vec2(top_radius - base_radius, 2.0 * half_height);
+	vec2 ca = vec2(q.x - min(q.x, (q.y < 0.0) ? base_radius : top_radius), abs(q.y) - half_height);
+	vec2 cb = q - k1 + k2 * clamp(dot(k1 - q, k2) / dot(k2, k2), 0.0, 1.0);
+	float s = (cb.x < 0.0 && ca.y < 0.0) ? -1.0 : 1.0;
+	return s * sqrt(min(dot(ca, ca), dot(cb, cb)));
+}
+
+// Facing up the y axis
+float sdf_plane(vec3 origin) {
+	return origin.y;
+}
+
+// Not exact, but a bound close enough to march with
+float sdf_ellipsoid(vec3 origin, vec3 radii) {
+	float k0 = length(origin / radii);
+	float k1 = length(origin / (radii * radii));
+	return k0 * (k0 - 1.0) / k1;
+}
+
+// radius is the distance from the centre to each flat side
+float sdf_hex_prism(vec3 origin, float radius, float half_height) {
+	const vec3 k = vec3(-0.8660254, 0.5, 0.57735);
+	vec3 p = abs(origin.xzy);
+	p.xy -= 2.0 * min(dot(k.xy, p.xy), 0.0) * k.xy;
+	vec2 d = vec2(length(p.xy - vec2(clamp(p.x, -k.z * radius, k.z * radius), radius)) * sign(p.y - radius), p.z - half_height);
+	return min(max(d.x, d.y), 0.0) + length(max(d, 0.0));
+}
+
+// A triply periodic minimal surface (SHAPE_TYPE_GYROID, SCHWARZ_P or SCHWARZ_D), thickened into walls. Fills all
+// of space, repeating every cell_size along each axis
+float sdf_tpms(vec3 origin, uint shape_type, vec3 cell_size, float thickness) {
+	vec3 freq = 2.0 * PI / cell_size;
+	vec3 q = origin * freq;
+	vec3 s = sin(q);
+	vec3 c = cos(q);
+
+	// The surface is where f is 0. Its gradient is at least min_grad on the surface
+	float f;
+	vec3 grad;
+	float min_grad;
+	if(shape_type == SHAPE_TYPE_GYROID) {
+		f = dot(s, c.yzx);
+		grad = c * c.yzx - s * s.zxy;
+		min_grad = 1.4142136;
+	} else if(shape_type == SHAPE_TYPE_SCHWARZ_P) {
+		f = c.x + c.y + c.z;
+		grad = -s;
+		min_grad = 1.0;
+	} else { // Assume SHAPE_TYPE_SCHWARZ_D
+		f = s.x * s.y * s.z + s.x * c.y * c.z + c.x * s.y * c.z + c.x * c.y * s.z;
+		grad = c * (s.yzx * s.zxy + c.yzx * c.zxy) - s * (s.yzx * c.zxy + c.yzx * s.zxy);
+		min_grad = 1.4142136;
+	}
+
+	// Dividing by the gradient gives the distance to the surface to first order, so the walls are the right thickness.
+	// Away from the walls it can overestimate, by up to about 1.5 times, so the distance is scaled down to stay a lower
+	// bound. Scaling doesn't move the walls
+	float dist = abs(f) / max(length(grad * freq), min_grad * min(freq.x, min(freq.y, freq.z)));
+	return (dist - thickness * 0.5) * 0.6;
+}
+
+// The height at texel coordinates uv of the heightmap layer, interpolated between the texels around it
+float sample_heightmap(vec2 uv, int layer) {
+	ivec2 size = textureSize(heightmaps, 0).xy;
+	uv = clamp(uv, vec2(0.0), vec2(size - 1));
+	ivec2 i = ivec2(floor(uv));
+	ivec2 j = min(i + 1, size - 1);
+	vec2 f = uv - vec2(i);
+
+	float h00 = texelFetch(heightmaps, ivec3(i.x, i.y, layer), 0).r;
+	float h10 = texelFetch(heightmaps, ivec3(j.x, i.y, layer), 0).r;
+	float h01 = texelFetch(heightmaps, ivec3(i.x, j.y, layer), 0).r;
+	float h11 = texelFetch(heightmaps, ivec3(j.x, j.y, layer), 0).r;
+	return mix(mix(h00, h10, f.x), mix(h01, h11, f.x), f.y);
+}
+
+// Terrain raised size.y above y = 0 where the heightmap is white, spanning size.xz either side of the centre. The
+// vertical distance to the surface overestimates the true distance on slopes, so is scaled by step_scale, which
+// allows for the heightmap's steepest slope
+float sdf_heightmap(vec3 origin, vec3 size, int layer, float step_scale) {
+	vec2 uv = (origin.xz / size.xz * 0.5 + 0.5) * vec2(textureSize(heightmaps, 0).xy - 1);
+	float surface = (origin.y - sample_heightmap(uv, layer) * size.y) * step_scale;
+
+	// Cut off at the terrain's extent, otherwise its edges would carry on forever
+	vec2 d = abs(origin.xz) - size.xz;
+	float sides = min(max(d.x, d.y), 0.0) + length(max(d, 0.0));
+	return max(surface, sides);
+}
+
+// The distance at grid coordinates g of the grid starting at z_offset in the volume atlas, interpolated between
+// the points around it
+float sample_volume(vec3 g, ivec3 size, int z_offset) {
+	g = clamp(g, vec3(0.0), vec3(size - 1));
+	ivec3 i = ivec3(floor(g));
+	ivec3 j = min(i + 1, size - 1);
+	vec3 f = g - vec3(i);
+	i.z += z_offset;
+	j.z += z_offset;
+
+	float d000 = texelFetch(volumes, ivec3(i.x, i.y, i.z), 0).r;
+	float d100 = texelFetch(volumes, ivec3(j.x, i.y, i.z), 0).r;
+	float d010 = texelFetch(volumes, ivec3(i.x, j.y, i.z), 0).r;
+	float d110 = texelFetch(volumes, ivec3(j.x, j.y, i.z), 0).r;
+	float d001 = texelFetch(volumes, ivec3(i.x, i.y, j.z), 0).r;
+	float d101 = texelFetch(volumes, ivec3(j.x, i.y, j.z), 0).r;
+	float d011 = texelFetch(volumes, ivec3(i.x, j.y, j.z), 0).r;
+	float d111 = texelFetch(volumes, ivec3(j.x, j.y, j.z), 0).r;
+	return mix(
+		mix(mix(d000, d100, f.x), mix(d010, d110, f.x), f.y),
+		mix(mix(d001, d101, f.x), mix(d011, d111, f.x), f.y),
+		f.z
+	);
+}
+
+// A signed distance grid whose first point is at grid.xyz, with points grid.w apart. placement.xyz is the number of
+// points along each axis and placement.w where the grid starts in the atlas. Outside the grid, the distance to its box
+// is combined with the distance from the nearest point on the box, which together bound the distance to the
+// surface inside it
+float sdf_volume(vec3 origin, vec4 grid, vec4 placement) {
+	ivec3 size = ivec3(placement.xyz);
+	vec3 g = (origin - grid.xyz) / grid.w;
+	vec3 inside = clamp(g, vec3(0.0), vec3(size - 1));
+	float dist = sample_volume(inside, size, int(placement.w));
+
+	float outside = length(g - inside);
+	if(outside > 0.0) {
+		dist = sqrt(outside * outside + max(dist, 0.0) * max(dist, 0.0));
+	}
+	return dist * grid.w;
+}
+
+// Points in a Mandelbox's orbit further than this from the centre are treated as having escaped
+const float MANDELBOX_BAILOUT = 100.0;
+
+float sdf_mandelbox(vec3 origin, float scale, float min_radius, float fixed_radius, uint iters, out vec4 aux) {
+	iters = min(iters, MAX_FRACTAL_ITERATIONS);
+
+	vec3 z = origin;
+	float dr = 1.0;
+	uint i = 0;
+	float point_trap = 1e20;
+	float plane_trap = 1e20;
+	for(; i < iters; i++) {
+		mandelbox_step(z, dr, origin, scale, min_radius, fixed_radius);
+
+		point_trap = min(point_trap, length(z));
+		plane_trap = min(plane_trap, min(abs(z.x), min(abs(z.y), abs(z.z))));
+		if(dot(z, z) > MANDELBOX_BAILOUT * MANDELBOX_BAILOUT) {
+			break;
+		}
+	}
+
+	aux = vec4(float(i) / float(iters), float(i) / float(iters), point_trap, plane_trap);
+	return length(z) / abs(dr);
+}
+
+// Folds space into one corner of the cube and scales it up each iteration, then measures the distance to the cube there
+// http://blog.hvidtfeldts.net/index.php/2011/08/distance-estimated-3d-fractals-iii-folding-space/
+float sdf_menger_sponge(vec3 origin, uint iters, float scale, vec3 offset, out vec4 aux) {
+	iters = min(iters, MAX_FRACTAL_ITERATIONS);
+
+	vec3 z = origin;
+	float total_scale = 1.0;
+	float point_trap = 1e20;
+	float plane_trap = 1e20;
+	for(uint i = 0; i < iters; i++) {
+		z = abs(z);
+		// Sort so x >= y >= z
+		if(z.x < z.y) z.xy = z.yx;
+		if(z.x < z.z) z.xz = z.zx;
+		if(z.y < z.z) z.yz = z.zy;
+
+		z = z * scale - offset * (scale - 1.0);
+		if(z.z < -0.5 * offset.z * (scale - 1.0)) {
+			z.z += offset.z * (scale - 1.0);
+		}
+		total_scale *= scale;
+
+		point_trap = min(point_trap, length(z));
+		plane_trap = min(plane_trap, min(abs(z.x), min(abs(z.y), abs(z.z))));
+	}
+
+	// Every point runs every iteration, so there's no escape time to colour by
+	aux = vec4(1.0, 1.0, point_trap, plane_trap);
+	return sdf_box(z, vec3(1.0)) / total_scale;
+}
+
+// Folds space onto one corner of the tetrahedron and scales it up each iteration
+// http://blog.hvidtfeldts.net/index.php/2011/08/distance-estimated-3d-fractals-iii-folding-space/
+float sdf_sierpinski_tetrahedron(vec3 origin, uint iters, float scale, out vec4 aux) {
+	iters = min(iters, MAX_FRACTAL_ITERATIONS);
+
+	vec3 z = origin;
+	float total_scale = 1.0;
+	float point_trap = 1e20;
+	float plane_trap = 1e20;
+	for(uint i = 0; i < iters; i++) {
+		// Reflect through the planes of symmetry onto the (1, 1, 1) corner
+		if(z.x + z.y < 0.0) z.xy = -z.yx;
+		if(z.x + z.z < 0.0) z.xz = -z.zx;
+		if(z.y + z.z < 0.0) z.zy = -z.yz;
+
+		z = z * scale - vec3(1.0) * (scale - 1.0);
+		total_scale *= scale;
+
+		point_trap = min(point_trap, length(z));
+		plane_trap = min(plane_trap, min(abs(z.x), min(abs(z.y), abs(z.z))));
+	}
+
+	aux = vec4(1.0, 1.0, point_trap, plane_trap);
+	return length(z) / total_scale;
+}
+
+// Squares a quaternion
+vec4 quat_square(vec4 q) {
+	return vec4(q.x * q.x - dot(q.yzw, q.yzw), 2.0 * q.x * q.yzw);
+}
+
+// https://iquilezles.org/articles/juliasets3d/
+float sdf_quaternion_julia(vec3 origin, vec4 c, uint iters, float bailout, out vec4 aux) {
+	iters = min(iters, MAX_FRACTAL_ITERATIONS);
+
+	vec4 z = vec4(origin, 0.0);
+	float dz2 = 1.0; // Squared length of the derivative
+	float z2 = dot(z, z);
+	uint i = 0;
+	float point_trap = 1e20;
+	float plane_trap = 1e20;
+	for(; i < iters; i++) {
+		dz2 *= 4.0 * z2;
+		z = quat_square(z) + c;
+		z2 = dot(z, z);
+
+		point_trap = min(point_trap, sqrt(z2));
+		plane_trap = min(plane_trap, min(abs(z.x), min(abs(z.y), abs(z.z))));
+		if(z2 > bailout * bailout) {
+			break;
+		}
+	}
+
+	float smooth_iters = float(i);
+	if(i < iters) {
+		smooth_iters += 1.0 - log(max(log(sqrt(z2)) / log(bailout), 1.0)) / log(2.0);
+	}
+	aux = vec4(float(i) / float(iters), clamp(smooth_iters / float(iters), 0.0, 1.0), point_trap, plane_trap);
+
+	return 0.25 * sqrt(z2 / dz2) * log(z2);
+}
+
+// Applies op to the point z, updating dr, the running derivative used to estimate the distance
+void apply_fractal_op(FractalOp op, inout vec3 z, inout float dr) {
+	if(op.op == FRACTAL_OP_BOX_FOLD) {
+		z = clamp(z, -op.params.x, op.params.x) * 2.0 - z;
+	} else if(op.op == FRACTAL_OP_SPHERE_FOLD) {
+		float min_radius2 = op.params.x * op.params.x;
+		float fixed_radius2 = op.params.y * op.params.y;
+		float r2 = dot(z, z);
+		float factor = (r2 < min_radius2) ? fixed_radius2 / min_radius2 : ((r2 < fixed_radius2) ? fixed_radius2 / r2 : 1.0);
+		z *= factor;
+		dr *= factor;
+	} else if(op.op == FRACTAL_OP_PLANE_REFLECTION) {
+		float dist = dot(z, op.params.xyz) - op.params.w;
+		z -= 2.0 * min(dist, 0.0) * op.params.xyz;
+	} else if(op.op == FRACTAL_OP_ROTATION) {
+		z = rotate(z, op.params);
+	} else if(op.op == FRACTAL_OP_SCALE) {
+		z *= op.params.x;
+		dr *= abs(op.params.x);
+	} else if(op.op == FRACTAL_OP_OFFSET) {
+		z += op.params.xyz;
+	}
+}
+
+// Kaleidoscopic IFS - Runs num_ops ops starting at first_op on the point each iteration, then measures the distance to the centre
+float sdf_kifs(vec3 origin, uint iters, float bailout, uint first_op, uint num_ops, out vec4 aux) {
+	iters = min(iters, MAX_FRACTAL_ITERATIONS);
+
+	vec3 z = origin;
+	float dr = 1.0;
+	uint i = 0;
+	float point_trap = 1e20;
+	float plane_trap = 1e20;
+	for(; i < iters; i++) {
+		for(uint j = 0; j < num_ops; j++) {
+			apply_fractal_op(fractal_op_buffer.ops[first_op + j], z, dr);
+		}
+
+		point_trap = min(point_trap, length(z));
+		plane_trap = min(plane_trap, min(abs(z.x), min(abs(z.y), abs(z.z))));
+		if(dot(z, z) > bailout * bailout) {
+			break;
+		}
+	}
+
+	aux = vec4(float(i) / float(iters), float(i) / float(iters), point_trap, plane_trap);
+	return length(z) / abs(dr);
+}
+
+// Runs the formula ops from first_op in turn, one per iteration, starting again from the first after the last.
+// Escape time fractals like this are estimated the same way as the Mandelbulb
+float sdf_hybrid(vec3 origin, uint iters, float bailout, uint first_op, uint num_ops, out vec4 aux) {
+	iters = min(iters, MAX_FRACTAL_ITERATIONS);
+
+	vec3 z = origin;
+	float dr = 1.0;
+	float r = 0.0;
+	uint i = 0;
+	float point_trap = 1e20;
+	float plane_trap = 1e20;
+	for(; i < iters; i++) {
+		r = length(z);
+		if(r > bailout) {
+			break;
+		}
+		point_trap = min(point_trap, r);
+		plane_trap = min(plane_trap, min(abs(z.x), min(abs(z.y), abs(z.z))));
+
+		FractalOp op = fractal_op_buffer.ops[first_op + i % num_ops];
+		if(op.op == FRACTAL_OP_MANDELBULB) {
+			mandelbulb_step(z, dr, origin, op.params.x);
+		} else { // Assume FRACTAL_OP_MANDELBOX
+			mandelbox_step(z, dr, origin, op.params.x, op.params.y, op.params.z);
+		}
+	}
+
+	aux = vec4(float(i) / float(iters), float(i) / float(iters), point_trap, plane_trap);
+
+	return 0.5 * log(r) * r / dr;
+}
+
+// Distance to the shape's primitive, with origin in the shape's local space. aux is set to the fractal data
+// for palettes, and zero for shapes that aren't fractals
+float sdf_shape(vec3 origin, Shape shape, out vec4 aux) {
+	aux = vec4(0.0);
+
+	if(shape.shape_type == SHAPE_TYPE_SPHERE) {
+		return sdf_sphere(origin, vec3(0.0), shape.size.x);
+	} else if(shape.shape_type == SHAPE_TYPE_WOBBLY_SPHERE) {
+		return sdf_wobbly_sphere(origin, vec3(0.0), shape.size.x);
+	} else if(shape.shape_type == SHAPE_TYPE_MANDELBULB) {
+		// Scaled by size, so the distance needs scaling back up
+		return sdf_mandelbulb(origin / shape.size.x, shape.params[0].x, uint(shape.params[0].y), shape.params[0].z, aux) * shape.size.x;
+	} else if(shape.shape_type == SHAPE_TYPE_BOX) {
+		return sdf_box(origin, shape.size);
+	} else if(shape.shape_type == SHAPE_TYPE_ROUNDED_BOX) {
+		return sdf_rounded_box(origin, shape.size, shape.params[0].x);
+	} else if(shape.shape_type == SHAPE_TYPE_TORUS) {
+		return sdf_torus(origin, shape.size.x, shape.size.y);
+	} else if(shape.shape_type == SHAPE_TYPE_CAPSULE) {
+		return sdf_capsule(origin, shape.size.x, shape.size.y);
+	} else if(shape.shape_type == SHAPE_TYPE_CYLINDER) {
+		return sdf_cylinder(origin, shape.size.x, shape.size.y);
+	} else if(shape.shape_type == SHAPE_TYPE_CONE) {
+		return sdf_cone(origin, shape.size.x, shape.params[0].x, shape.size.y);
+	} else if(shape.shape_type == SHAPE_TYPE_PLANE) {
+		return sdf_plane(origin);
+	} else if(shape.shape_type == SHAPE_TYPE_ELLIPSOID) {
+		return sdf_ellipsoid(origin, shape.size);
+	} else if(shape.shape_type == SHAPE_TYPE_HEX_PRISM) {
+		return sdf_hex_prism(origin, shape.size.x, shape.size.y);
+	} else if(shape.shape_type == SHAPE_TYPE_MANDELBOX) {
+		return sdf_mandelbox(origin / shape.size.x, shape.params[0].x, shape.params[0].y, shape.params[0].z, uint(shape.params[0].w), aux) * shape.size.x;
+	} else if(shape.shape_type == SHAPE_TYPE_MENGER_SPONGE) {
+		return sdf_menger_sponge(origin / shape.size.x, uint(shape.params[0].x), shape.params[0].y, shape.params[1].xyz, aux) * shape.size.x;
+	} else if(shape.shape_type == SHAPE_TYPE_SIERPINSKI_TETRAHEDRON) {
+		return sdf_sierpinski_tetrahedron(origin / shape.size.x, uint(shape.params[0].x), shape.params[0].y, aux) * shape.size.x;
+	} else if(shape.shape_type == SHAPE_TYPE_QUATERNION_JULIA) {
+		return sdf_quaternion_julia(origin / shape.size.x, shape.params[0], uint(shape.params[1].x), shape.params[1].y, aux) * shape.size.x;
+	} else if(shape.shape_type == SHAPE_TYPE_KIFS) {
+		return sdf_kifs(origin / shape.size.x, uint(shape.params[0].x), shape.params[0].y, uint(shape.params[1].x), uint(shape.params[1].y), aux) * shape.size.x;
+	} else if(shape.shape_type == SHAPE_TYPE_HYBRID) {
+		return sdf_hybrid(origin / shape.size.x, uint(shape.params[0].x), shape.params[0].y, uint(shape.params[1].x), uint(shape.params[1].y), aux) * shape.size.x;
+	} else if(shape.shape_type == SHAPE_TYPE_GYROID || shape.shape_type == SHAPE_TYPE_SCHWARZ_P || shape.shape_type == SHAPE_TYPE_SCHWARZ_D) {
+		return sdf_tpms(origin, shape.shape_type, shape.size, shape.params[0].x);
+	} else if(shape.shape_type == SHAPE_TYPE_HEIGHTMAP) {
+		return sdf_heightmap(origin, shape.size, int(shape.params[0].x), shape.params[0].y);
+	} else if(shape.shape_type == SHAPE_TYPE_MESH || shape.shape_type == SHAPE_TYPE_BAKED) {
+		return sdf_volume(origin, shape.params[0], shape.params[1]);
+	} else {
+		return 999.0;//1.0 / 0.0; // Infinity
+	}
+}
+
+// Scrambles the bits of x so nearby inputs give unrelated outputs - https://nullprogram.com/blog/2018/07/31/
+uint hash_bits(uint x) {
+	x ^= x >> 16;
+	x *= 0x7feb352du;
+	x ^= x >> 15;
+	x *= 0x846ca68bu;
+	x ^= x >> 16;
+	return x;
+}
+
+// Hashes a corner of the noise grid, differently for each scene.noise_seed
+uint hash(ivec3 cell) {
+	uint h = hash_bits(scene.noise_seed);
+	h = hash_bits(h ^ uint(cell.x));
+	h = hash_bits(h ^ uint(cell.y));
+	return hash_bits(h ^ uint(cell.z));
+}
+
+// A random value from -1 to 1 for a corner of the noise grid
+float hash_value(ivec3 cell) {
+	return float(hash(cell)) / 2147483647.5 - 1.0;
+}
+
+// The dot product of d with a random gradient for a corner of the noise grid, one of the 12 directions to
+// the edges of a cube - https://mrl.cs.nyu.edu/~perlin/paper445.pdf
+float hash_gradient(ivec3 cell, vec3 d) {
+	uint h = hash(cell) & 15u;
+	float u = (h < 8u) ? d.x : d.y;
+	float v = (h < 4u) ? d.y : ((h == 12u || h == 14u) ? d.x : d.z);
+	return (((h & 1u) == 0u) ? u : -u) + (((h & 2u) == 0u) ? v : -v);
+}
+
+// The noise functions all go from about -1 to 1, with a feature roughly every unit
+
+// Interpolates random values at the corners of the grid - https://iquilezles.org/articles/morenoise/
+float value_noise(vec3 p) {
+	ivec3 i = ivec3(floor(p));
+	vec3 f = fract(p);
+	vec3 u = f * f * (3.0 - 2.0 * f);
+
+	return mix(
+		mix(mix(hash_value(i), hash_value(i + ivec3(1, 0, 0)), u.x), mix(hash_value(i + ivec3(0, 1, 0)), hash_value(i + ivec3(1, 1, 0)), u.x), u.y),
+		mix(mix(hash_value(i + ivec3(0, 0, 1)), hash_value(i + ivec3(1, 0, 1)), u.x), mix(hash_value(i + ivec3(0, 1, 1)), hash_value(i + ivec3(1, 1, 1)), u.x), u.y),
+		u.z
+	);
+}
+
+// Perlin's improved noise, interpolating random gradients at the corners of the grid
+float gradient_noise(vec3 p) {
+	ivec3 i = ivec3(floor(p));
+	vec3 f = fract(p);
+	vec3 u = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+
+	return mix(
+		mix(
+			mix(hash_gradient(i, f), hash_gradient(i + ivec3(1, 0, 0), f - vec3(1, 0, 0)), u.x),
+			mix(hash_gradient(i + ivec3(0, 1, 0), f - vec3(0, 1, 0)), hash_gradient(i + ivec3(1, 1, 0), f - vec3(1, 1, 0)), u.x),
+			u.y
+		),
+		mix(
+			mix(hash_gradient(i + ivec3(0, 0, 1), f - vec3(0, 0, 1)), hash_gradient(i + ivec3(1, 0, 1), f - vec3(1, 0, 1)), u.x),
+			mix(hash_gradient(i + ivec3(0, 1, 1), f - vec3(0, 1, 1)), hash_gradient(i + ivec3(1, 1, 1), f - vec3(1, 1, 1)), u.x),
+			u.y
+		),
+		u.z
+	);
+}
+
+// Sums random gradients at the corners of the simplex (tetrahedron) containing p, each fading out with distance -
+// https://weber.itn.liu.se/~stegu/simplexnoise/simplexnoise.pdf. Fades out within sqrt(0.5) rather than the paper's
+// sqrt(0.6), which overlaps into neighbouring simplices and leaves seams
+float simplex_noise(vec3 p) {
+	// Skew to the grid of cubes the simplices tile, then find which of the cube's 6 simplices p is in
+	vec3 s = floor(p + dot(p, vec3(1.0 / 3.0)));
+	vec3 x0 = p - s + dot(s, vec3(1.0 / 6.0));
+	vec3 e = step(x0.yzx, x0);
+	vec3 i1 = e * (1.0 - e.zxy);
+	vec3 i2 = 1.0 - e.zxy * (1.0 - e);
+
+	vec3 x1 = x0 - i1 + 1.0 / 6.0;
+	vec3 x2 = x0 - i2 + 2.0 / 6.0;
+	vec3 x3 = x0 - 0.5;
+
+	ivec3 cell = ivec3(s);
+	vec4 w = max(0.5 - vec4(dot(x0, x0), dot(x1, x1), dot(x2, x2), dot(x3, x3)), 0.0);
+	w *= w;
+	w *= w;
+	vec4 d = vec4(hash_gradient(cell, x0), hash_gradient(cell + ivec3(i1), x1), hash_gradient(cell + ivec3(i2), x2), hash_gradient(cell + ivec3(1), x3));
+
+	return 76.0 * dot(w, d);
+}
+
+float noise(vec3 p, uint noise_type) {
+	if(noise_type == NOISE_GRADIENT) {
+		return gradient_noise(p);
+	} else if(noise_type == NOISE_SIMPLEX) {
+		return simplex_noise(p);
+	}
+
+	return value_noise(p);
+}
+
+// Fractal Brownian motion - Sums octaves of noise, each finer and fainter than the last by the scene's lacunarity
+// and gain. Divided by the total amplitude so it stays within the same range as a single octave
+float fbm(vec3 p, uint noise_type, uint octaves) {
+	float sum = 0.0;
+	float amplitude = 1.0;
+	float total = 0.0;
+	for(uint i = 0; i < min(octaves, MAX_NOISE_OCTAVES); i++) {
+		sum += amplitude * noise(p, noise_type);
+		total += amplitude;
+		amplitude *= scene.noise_gain;
+		p *= scene.noise_lacunarity;
+	}
+
+	return sum / total;
+}
+
+// How many times steeper fbm can be than a single octave of noise
+float fbm_slope(uint octaves) {
+	float slope = 0.0;
+	float amplitude = 1.0;
+	float total = 0.0;
+	for(uint i = 0; i < min(octaves, MAX_NOISE_OCTAVES); i++) {
+		slope += amplitude * pow(scene.noise_lacunarity, float(i));
+		total += amplitude;
+		amplitude *= scene.noise_gain;
+	}
+
+	return slope / total;
+}
+
+// Deforms the point before the shape is evaluated, for the modifiers that do
+vec3 apply_point_modifier(Modifier modifier, vec3 p) {
+	if(modifier.op == MODIFIER_TWIST) {
+		float angle = modifier.params.x * p.y;
+		float c = cos(angle);
+		float s = sin(angle);
+		p.xz = mat2(c, s, -s, c) * p.xz;
+	} else if(modifier.op == MODIFIER_BEND) {
+		float angle = modifier.params.x * p.x;
+		float c = cos(angle);
+		float s = sin(angle);
+		p.xy = mat2(c, s, -s, c) * p.xy;
+	}
+
+	return p;
+}
+
+// Deforms the distance to the shape at p after it's evaluated, for the modifiers that do
+float apply_distance_modifier(Modifier modifier, vec3 p, float dist) {
+	if(modifier.op == MODIFIER_SINE_DISPLACEMENT) {
+		vec3 wave = sin(p * modifier.params.y);
+		dist += modifier.params.x * wave.x * wave.y * wave.z;
+	} else if(modifier.op == MODIFIER_NOISE_DISPLACEMENT) {
+		dist += modifier.params.x * fbm(p * modifier.params.y, modifier.noise_type, modifier.octaves);
+	} else if(modifier.op == MODIFIER_ROUND) {
+		dist -= modifier.params.x;
+	} else if(modifier.op == MODIFIER_ONION) {
+		dist = abs(dist) - modifier.params.x;
+	}
+
+	return dist;
+}
+
+// Varies the colour of the shape at p, for the modifiers that do
+vec3 apply_colour_modifier(Modifier modifier, vec3 p, vec3 colour) {
+	if(modifier.op == MODIFIER_COLOUR_NOISE) {
+		float t = fbm(p * modifier.params.y, modifier.noise_type, modifier.octaves) * 0.5 + 0.5;
+		colour = mix(colour, modifier.colour, clamp(modifier.params.x * t, 0.0, 1.0));
+	}
+
+	return colour;
+}
+
+// Maps the fractal data from sdf_shape to a colour along the palette's gradient
+vec3 sample_palette(Palette palette, vec4 aux) {
+	float value = aux.y;
+	if(palette.source == PALETTE_SOURCE_ITERATIONS) {
+		value = aux.x;
+	} else if(palette.source == PALETTE_SOURCE_POINT_TRAP) {
+		value = aux.z;
+	} else if(palette.source == PALETTE_SOURCE_PLANE_TRAP) {
+		value = aux.w;
+	}
+
+	float t = value * palette.scale + palette.offset;
+	t = (palette.repeat != 0) ? fract(t) : clamp(t, 0.0, 1.0);
+
+	vec3 colour = palette.stops[0].rgb;
+	for(uint i = 1; i < min(palette.num_stops, MAX_PALETTE_STOPS); i++) {
+		vec4 prev = palette.stops[i - 1];
+		vec4 next = palette.stops[i];
+		if(t >= prev.a) {
+			colour = mix(prev.rgb, next.rgb, clamp((t - prev.a) / max(next.a - prev.a, 1e-6), 0.0, 1.0));
+		}
+	}
+
+	return colour;
+}
+
+// Colour of and distance to the shape at shape_index, with origin in world space
+vec4 sdf_scene_shape(vec3 origin, uint shape_index) {
+	Shape shape = shape_buffer.shapes[shape_index];
+	vec4 local = to_local_space(origin, shape_index);
+
+	vec3 p = local.xyz;
+	float dist_scale = local.w;
+	for(uint i = 0; i < shape.num_modifiers; i++) {
+		Modifier modifier = modifier_buffer.modifiers[shape.first_modifier + i];
+		p = apply_point_modifier(modifier, p);
+		if(modifier.op == MODIFIER_NOISE_DISPLACEMENT) {
+			// params.w allows for a single octave, the finer octaves of FBM are steeper
+			dist_scale /= 1.0 + (1.0 / modifier.params.w - 1.0) * fbm_slope(modifier.octaves);
+		} else {
+			dist_scale *= modifier.params.w;
+		}
+	}
+
+	vec4 aux;
+	float dist = sdf_shape(p, shape, aux);
+	vec3 colour = (shape.palette >= 0) ? sample_palette(palette_buffer.palettes[shape.palette], aux) : shape.albedo;
+	for(uint i = 0; i < shape.num_modifiers; i++) {
+		Modifier modifier = modifier_buffer.modifiers[shape.first_modifier + i];
+		dist = apply_distance_modifier(modifier, p, dist);
+		colour = apply_colour_modifier(modifier, p, colour);
+	}
+	dist *= dist_scale;
+
+	return vec4(colour, dist);
+}
+
+// Runs the CSG program to find the distance to and colour of the scene
+vec4 sdf_scene(vec3 origin) {
+	vec4 stack[CSG_STACK_SIZE];
+	uint top = 0;
+
+	for(uint i = 0; i < scene.num_instructions; i++) {
+		CsgInstruction instruction = instruction_buffer.instructions[i];
+
+		if(instruction.op == CSG_OP_PUSH_SHAPE) {
+			stack[top] = sdf_scene_shape(origin, instruction.shape_index);
+			top++;
+		} else { // Assume CSG_OP_COMBINE
+			top--;
+			vec4 a = stack[top - 1];
+			vec4 b = stack[top];
+			stack[top - 1] = combine(a.w, a.xyz, b.w, b.xyz, instruction.blend_mode, instruction.blend_strength);
+		}
+	}
+
+	if(top == 0) {
+		return vec4(0, 0, 0, 1.0 / 0.0);
+	}
+
+	return stack[0];
+}
@@ -1,10 +1,44 @@
-use std::sync::Arc;
+mod scene;
+mod shape;
+mod csg;
+mod palette;
+mod fractal;
+mod domain;
+mod modifier;
+mod noise;
+mod heightmap;
+mod mesh;
+mod sdf_grid;
+mod volume;
+mod export;
+mod occlusion;
+mod light;
+mod material;
 
-use vulkano::{device::DeviceExtensions, buffer::{CpuAccessibleBuffer, BufferUsage}, image::{StorageImage, ImageDimensions, view::ImageView}, format::Format, pipeline::{ComputePipeline, Pipeline, PipelineBindPoint}, descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet}, command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage, CopyImageToBufferInfo}, sync::{self, GpuFuture}};
+use std::{sync::Arc, path::{Path, PathBuf}};
 
-use crate::{vulkan_computil::{VkInstance, VkTarget, VK_QUEUEFLAGS_COMPUTE}};
+use vulkano::{device::{Device, DeviceExtensions}, buffer::{CpuAccessibleBuffer, BufferUsage}, image::{StorageImage, ImageDimensions, view::ImageView}, format::Format, pipeline::{ComputePipeline, Pipeline, PipelineBindPoint}, descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet}, command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage, CopyImageToBufferInfo}, sync::{self, GpuFuture}};
 
-use self::shaders::ray_marching_shader::ty::{SceneInfo, Shape, DebugInfo};
+use crate::{vulkan_computil::{VkInstance, VkTarget, VkStorageVec, VK_QUEUEFLAGS_COMPUTE}};
+
+use self::shaders::ray_marching_shader::ty::{SceneInfo, Shape, CsgInstruction, Palette, FractalOp, Modifier, Light, Material, DebugInfo};
+
+pub use self::scene::{SceneDesc, SceneBuilder, SceneError};
+pub use self::shape::{ShapeDesc, ShapeKind, BlendMode, ShapeError, axis_angle};
+pub use self::csg::{CsgNode, GroupDesc};
+pub use self::palette::{PaletteDesc, PaletteSource, PaletteStop, PaletteError};
+pub use self::fractal::{FractalOpDesc, HybridFormula, HybridStep};
+pub use self::domain::DomainOp;
+pub use self::modifier::ModifierDesc;
+pub use self::noise::{NoiseKind, NoiseSettings};
+pub use self::occlusion::AmbientOcclusion;
+pub use self::light::{LightDesc, LightKind};
+pub use self::material::MaterialDesc;
+pub use self::export::{GridRegion, ExportError};
+pub use self::mesh::{Mesh, MeshError};
+pub use self::sdf_grid::{SdfGrid, GridError};
+
+use self::{scene::SceneWatcher, csg::FlatScene, heightmap::HeightmapArray, volume::VolumeAtlas, mesh::MeshFormat, shaders::sdf_grid_shader::ty::GridInfo};
 
 mod shaders {
 	pub mod ray_marching_shader {
@@ -18,11 +52,29 @@ mod shaders {
 			}
 		}
 	}
+
+	/// Samples the scene's distance function on a grid. Binds the same scene buffers as the ray marching shader
+	pub mod sdf_grid_shader {
+		vulkano_shaders::shader! {
+			ty: "compute",
+			path: "shaders/sdf_grid_shader.comp",
+			types_meta: {
+				use bytemuck::{Zeroable, Pod};
+
+				#[derive(Clone, Copy, Zeroable, Pod)]
+			}
+		}
+	}
 }
 
 pub const RESULT_IMG_WIDTH: u32 = 512;
 pub const RESULT_IMG_HEIGHT: u32 = 512;
 
+/// The most points of a grid sampled at once, which bounds the size of the buffer they're read back through
+const MAX_GRID_SLAB_POINTS: u32 = 1 << 22;
+
+const DEFAULT_SCENE: &str = include_str!("../scenes/default.ron");
+
 #[allow(unused)]
 const SHAPE_TYPE_NONE: u32 = 0;
 #[allow(unused)]
@@ -31,16 +83,240 @@ const SHAPE_TYPE_SPHERE: u32 = 1;
 const SHAPE_TYPE_WOBBLY_SPHERE: u32 = 2;
 #[allow(unused)]
 const SHAPE_TYPE_MANDELBULB: u32 = 3;
+const SHAPE_TYPE_BOX: u32 = 4;
+const SHAPE_TYPE_ROUNDED_BOX: u32 = 5;
+const SHAPE_TYPE_TORUS: u32 = 6;
+const SHAPE_TYPE_CAPSULE: u32 = 7;
+const SHAPE_TYPE_CYLINDER: u32 = 8;
+const SHAPE_TYPE_CONE: u32 = 9;
+const SHAPE_TYPE_PLANE: u32 = 10;
+const SHAPE_TYPE_ELLIPSOID: u32 = 11;
+const SHAPE_TYPE_HEX_PRISM: u32 = 12;
+const SHAPE_TYPE_MANDELBOX: u32 = 13;
+const SHAPE_TYPE_MENGER_SPONGE: u32 = 14;
+const SHAPE_TYPE_SIERPINSKI_TETRAHEDRON: u32 = 15;
+const SHAPE_TYPE_QUATERNION_JULIA: u32 = 16;
+const SHAPE_TYPE_KIFS: u32 = 17;
+const SHAPE_TYPE_HYBRID: u32 = 18;
+const SHAPE_TYPE_GYROID: u32 = 19;
+const SHAPE_TYPE_SCHWARZ_P: u32 = 20;
+const SHAPE_TYPE_SCHWARZ_D: u32 = 21;
+const SHAPE_TYPE_HEIGHTMAP: u32 = 22;
+const SHAPE_TYPE_MESH: u32 = 23;
+const SHAPE_TYPE_BAKED: u32 = 24;
+
+#[allow(unused)]
+const BLEND_MODE_NONE: u32 = 0;
+#[allow(unused)]
+const BLEND_MODE_INTERSECTION: u32 = 1;
+#[allow(unused)]
+const BLEND_MODE_DIFFERENCE: u32 = 2;
+#[allow(unused)]
+const BLEND_MODE_SMOOTH: u32 = 3;
+
+const OUTPUT_MODE_SHADED: u32 = 0;
+const OUTPUT_MODE_AMBIENT_OCCLUSION: u32 = 1;
+
+const LIGHT_POINT: u32 = 0;
+const LIGHT_DIRECTIONAL: u32 = 1;
+const LIGHT_SPOT: u32 = 2;
+
+const CSG_OP_PUSH_SHAPE: u32 = 0;
+const CSG_OP_COMBINE: u32 = 1;
+
+const PALETTE_SOURCE_ITERATIONS: u32 = 0;
+const PALETTE_SOURCE_SMOOTH_ITERATIONS: u32 = 1;
+const PALETTE_SOURCE_POINT_TRAP: u32 = 2;
+const PALETTE_SOURCE_PLANE_TRAP: u32 = 3;
+
+const FRACTAL_OP_BOX_FOLD: u32 = 0;
+const FRACTAL_OP_SPHERE_FOLD: u32 = 1;
+const FRACTAL_OP_PLANE_REFLECTION: u32 = 2;
+const FRACTAL_OP_ROTATION: u32 = 3;
+const FRACTAL_OP_SCALE: u32 = 4;
+const FRACTAL_OP_OFFSET: u32 = 5;
+const FRACTAL_OP_MANDELBULB: u32 = 6;
+const FRACTAL_OP_MANDELBOX: u32 = 7;
+
+const DOMAIN_OP_NONE: u32 = 0;
+const DOMAIN_OP_REPEAT: u32 = 1;
+const DOMAIN_OP_REPEAT_LIMITED: u32 = 2;
+const DOMAIN_OP_MIRROR: u32 = 3;
+const DOMAIN_OP_POLAR: u32 = 4;
+
+const MODIFIER_TWIST: u32 = 0;
+const MODIFIER_BEND: u32 = 1;
+const MODIFIER_SINE_DISPLACEMENT: u32 = 2;
+const MODIFIER_NOISE_DISPLACEMENT: u32 = 3;
+const MODIFIER_ROUND: u32 = 4;
+const MODIFIER_ONION: u32 = 5;
+const MODIFIER_COLOUR_NOISE: u32 = 6;
+
+const NOISE_VALUE: u32 = 0;
+const NOISE_GRADIENT: u32 = 1;
+const NOISE_SIMPLEX: u32 = 2;
 
 impl Default for Shape {
 	fn default() -> Self {
 		Self {
 			position: Default::default(),
+			shape_type: Default::default(),
+			rotation: Default::default(),
+			scale: Default::default(),
+			parent: Default::default(),
 			size: Default::default(),
+			domain_op: Default::default(),
 			albedo: Default::default(),
-			shape_type: Default::default(),
+			// 0 would be the first palette
+			palette: -1,
+			params: Default::default(),
+			domain_params: Default::default(),
+			first_modifier: Default::default(),
+			num_modifiers: Default::default(),
+			// 0 would be the first material
+			material: -1,
+			_dummy0: Default::default(),
+		}
+	}
+}
+
+impl Default for CsgInstruction {
+	fn default() -> Self {
+		Self {
+			op: Default::default(),
+			shape_index: Default::default(),
+			blend_mode: Default::default(),
+			blend_strength: Default::default(),
+		}
+	}
+}
+
+impl Default for Palette {
+	fn default() -> Self {
+		Self {
+			stops: Default::default(),
+			source: Default::default(),
+			num_stops: Default::default(),
+			scale: Default::default(),
+			offset: Default::default(),
+			repeat: Default::default(),
+			_dummy0: Default::default(),
+		}
+	}
+}
+
+impl Default for FractalOp {
+	fn default() -> Self {
+		Self {
+			params: Default::default(),
+			op: Default::default(),
+			_dummy0: Default::default(),
+		}
+	}
+}
+
+impl Default for Modifier {
+	fn default() -> Self {
+		Self {
+			params: Default::default(),
+			colour: Default::default(),
+			noise_type: Default::default(),
+			op: Default::default(),
+			octaves: Default::default(),
 			_dummy0: Default::default(),
-			_dummy1: Default::default(),
+		}
+	}
+}
+
+impl Default for Light {
+	fn default() -> Self {
+		Self {
+			position: Default::default(),
+			light_type: Default::default(),
+			direction: Default::default(),
+			intensity: Default::default(),
+			colour: Default::default(),
+			range: Default::default(),
+			falloff: Default::default(),
+			cos_inner: Default::default(),
+			cos_outer: Default::default(),
+			shadow_softness: Default::default(),
+			shadow_steps: Default::default(),
+			_dummy0: Default::default(),
+		}
+	}
+}
+
+impl Default for Material {
+	fn default() -> Self {
+		Self {
+			emissive: Default::default(),
+			roughness: Default::default(),
+			metallic: Default::default(),
+			_dummy0: Default::default(),
+		}
+	}
+}
+
+/// The storage buffers a scene is flattened into. The lights and materials are only bound by the ray marching shader,
+/// as only it shades the scene
+struct SceneBuffers {
+	shapes: VkStorageVec<Shape>,
+	instructions: VkStorageVec<CsgInstruction>,
+	palettes: VkStorageVec<Palette>,
+	fractal_ops: VkStorageVec<FractalOp>,
+	modifiers: VkStorageVec<Modifier>,
+	materials: VkStorageVec<Material>,
+	lights: VkStorageVec<Light>
+}
+
+impl SceneBuffers {
+	fn new(device: Arc<Device>, flat: FlatScene, lights: Vec<Light>) -> Self {
+		SceneBuffers {
+			shapes: VkStorageVec::new(device.clone(), flat.shapes),
+			instructions: VkStorageVec::new(device.clone(), flat.instructions),
+			palettes: VkStorageVec::new(device.clone(), flat.palettes),
+			fractal_ops: VkStorageVec::new(device.clone(), flat.fractal_ops),
+			modifiers: VkStorageVec::new(device.clone(), flat.modifiers),
+			materials: VkStorageVec::new(device.clone(), flat.materials),
+			lights: VkStorageVec::new(device, lights)
+		}
+	}
+
+	/// Replaces the contents of the buffers. Returns true if any of the buffers had to be reallocated
+	fn upload(&mut self, flat: FlatScene) -> bool {
+		self.shapes.set(flat.shapes);
+		self.instructions.set(flat.instructions);
+		self.palettes.set(flat.palettes);
+		self.fractal_ops.set(flat.fractal_ops);
+		self.modifiers.set(flat.modifiers);
+		self.materials.set(flat.materials);
+
+		// Not short-circuiting, as every buffer needs uploading
+		self.shapes.upload() | self.instructions.upload() | self.palettes.upload() | self.fractal_ops.upload() | self.modifiers.upload() | self.materials.upload()
+	}
+
+	/// Replaces the lights. Returns true if their buffer had to be reallocated
+	fn upload_lights(&mut self, lights: Vec<Light>) -> bool {
+		self.lights.set(lights);
+		self.lights.upload()
+	}
+}
+
+/// What the renderer draws for each pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+	/// The lit scene
+	Shaded,
+	/// Only the ambient occlusion, from black where fully occluded to white, for inspecting it alone
+	AmbientOcclusion
+}
+
+impl OutputMode {
+	fn to_u32(self) -> u32 {
+		match self {
+			OutputMode::Shaded => OUTPUT_MODE_SHADED,
+			OutputMode::AmbientOcclusion => OUTPUT_MODE_AMBIENT_OCCLUSION
 		}
 	}
 }
@@ -50,59 +326,56 @@ pub struct Raymarch {
 	vk_target: VkTarget,
 	pub _info_buffer: Arc<CpuAccessibleBuffer<SceneInfo>>,
 	image: Arc<StorageImage>,
+	image_view: Arc<ImageView<StorageImage>>,
+	scene_buffers: SceneBuffers,
+	heightmaps: HeightmapArray,
+	volumes: VolumeAtlas,
 	output_buffer: Arc<CpuAccessibleBuffer<[u8]>>,
 	pub debug_buffer: Arc<CpuAccessibleBuffer<DebugInfo>>,
 	compute_pipeline: Arc<ComputePipeline>,
-	descriptor_set: Arc<PersistentDescriptorSet>
+	descriptor_set: Arc<PersistentDescriptorSet>,
+	grid_pipeline: Arc<ComputePipeline>,
+	scene_watcher: Option<SceneWatcher>,
+	/// Kept across scene changes, as is whether shadows are turned on
+	output_mode: OutputMode,
+	shadows: bool,
+	/// The scene as last set, kept in sync with the nodes added or removed since
+	scene: SceneDesc
 }
 
 impl Raymarch {
+	/// Creates a renderer showing the default scene (scenes/default.ron)
 	pub fn new() -> Self {
+		let scene = SceneDesc::parse(DEFAULT_SCENE).expect("Failed to parse default scene");
+		Self::with_scene(scene, None).expect("Failed to lay out default scene")
+	}
+
+	/// Creates a renderer showing a scene built in code, see [`SceneBuilder`]
+	pub fn from_scene_desc(scene: SceneDesc) -> Result<Self, SceneError> {
+		Self::with_scene(scene, None)
+	}
+
+	/// Creates a renderer showing the scene described by the scene file at `path`. The file is watched
+	/// for changes, see [`Raymarch::reload_scene_if_changed`]
+	pub fn from_scene<P: AsRef<Path>>(path: P) -> Result<Self, SceneError> {
+		let mut scene_watcher = SceneWatcher::new(&path);
+		let scene = scene_watcher.load()?;
+		Self::with_scene(scene, Some(scene_watcher))
+	}
+
+	fn with_scene(scene: SceneDesc, scene_watcher: Option<SceneWatcher>) -> Result<Self, SceneError> {
+		let mut flat = scene.flatten()?;
+		let data = scene.to_scene_info(&flat);
+		let lights = light::to_lights(&scene.lights)?;
+
 		let vk_instance = VkInstance::new(None);
 
 		let vk_target = VkTarget::new(vk_instance.clone(), VK_QUEUEFLAGS_COMPUTE, DeviceExtensions::default());
 
-		let data: SceneInfo = SceneInfo {
-			camera_pos: [0., 0., 10.],
-			look_at: [0., 0., 0.],
-			canvas_dist: 10.,
-			num_shapes: 3,
-			point_light: [0., 100., 200.],
-			light_colour: [1.0, 1.0, 1.0],
-			shapes: [
-				Shape {
-					position: [0., 0., 0.],
-					size: [0.6, 0.6, 0.6],
-					albedo: [0.1, 0.0, 0.2],
-					shape_type: SHAPE_TYPE_MANDELBULB,
-					_dummy0: [0; 4],
-					_dummy1: [0; 4],
-				},
-				Shape {
-					position: [1.6, 0., 0.],
-					size: [1., 1., 1.],
-					albedo: [0.0, 0.4, 0.8],
-					shape_type: SHAPE_TYPE_SPHERE,
-					_dummy0: [0; 4],
-					_dummy1: [0; 4],
-				},
-				Shape {
-					position: [-1.2, 0.5, -0.4],
-					size: [1.80, 1.80, 1.80],
-					albedo: [0.0, 1.0, 0.8],
-					shape_type: SHAPE_TYPE_SPHERE,
-					_dummy0: [0; 4],
-					_dummy1: [0; 4],
-				},
-				{ Default::default() },
-				{ Default::default() }, { Default::default() }, { Default::default() },
-				{ Default::default() }, { Default::default() }, { Default::default() }
-			],
-			_dummy0: [0; 4],
-			_dummy1: [0; 12],
-			_dummy2: [0; 4],
-    		_dummy3: [0; 4],
-		};
+		let heightmaps = HeightmapArray::load(&vk_target, &flat.heightmaps)?;
+		heightmaps.set_step_scales(&mut flat.shapes);
+		let volumes = VolumeAtlas::load(&vk_target, &flat.volumes)?;
+		volumes.set_volume_params(&mut flat.shapes);
 
 		let info_buffer = CpuAccessibleBuffer::from_data(
 			vk_target.device.clone(),
@@ -122,6 +395,8 @@ impl Raymarch {
 		).expect("Failed to create storage image");
 		let image_view = ImageView::new_default(image.clone()).unwrap();
 
+		let scene_buffers = SceneBuffers::new(vk_target.device.clone(), flat, lights);
+
 		let output_buffer = CpuAccessibleBuffer::from_iter(
 			vk_target.device.clone(),
 			BufferUsage { transfer_dst: true, ..Default::default() },
@@ -149,26 +424,332 @@ impl Raymarch {
 			&(), None, |_| {}
 		).expect("Failed to create pipeline");
 
-		let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
-		let set = PersistentDescriptorSet::new(
-			layout.clone(),
-			[
-				WriteDescriptorSet::buffer(0, info_buffer.clone()),
-				WriteDescriptorSet::image_view(1, image_view),
-				WriteDescriptorSet::buffer(2, debug_buffer.clone())
-			]
-		).unwrap();
+		let grid_shader = shaders::sdf_grid_shader::load(vk_target.device.clone()).expect("Failed to load shader");
 
-		Self {
+		let grid_pipeline = ComputePipeline::new(vk_target.device.clone(),
+			grid_shader.entry_point("main").unwrap(),
+			&(), None, |_| {}
+		).expect("Failed to create pipeline");
+
+		let set = Self::create_descriptor_set(&compute_pipeline, &info_buffer, &image_view, &debug_buffer, &scene_buffers, &heightmaps, &volumes);
+
+		Ok(Self {
 			_vk_instance: vk_instance,
 			vk_target,
 			_info_buffer: info_buffer,
 			image,
+			image_view,
+			scene_buffers,
+			heightmaps,
+			volumes,
 			output_buffer,
 			debug_buffer,
 			compute_pipeline,
-			descriptor_set: set
+			descriptor_set: set,
+			grid_pipeline,
+			scene_watcher,
+			output_mode: OutputMode::Shaded,
+			shadows: true,
+			scene
+		})
+	}
+
+	fn create_descriptor_set(
+		compute_pipeline: &Arc<ComputePipeline>,
+		info_buffer: &Arc<CpuAccessibleBuffer<SceneInfo>>,
+		image_view: &Arc<ImageView<StorageImage>>,
+		debug_buffer: &Arc<CpuAccessibleBuffer<DebugInfo>>,
+		scene_buffers: &SceneBuffers,
+		heightmaps: &HeightmapArray,
+		volumes: &VolumeAtlas
+	) -> Arc<PersistentDescriptorSet> {
+		let layout = compute_pipeline.layout().set_layouts().get(0).unwrap();
+		PersistentDescriptorSet::new(
+			layout.clone(),
+			[
+				WriteDescriptorSet::image_view(1, image_view.clone()),
+				WriteDescriptorSet::buffer(2, debug_buffer.clone()),
+				WriteDescriptorSet::buffer(10, scene_buffers.lights.buffer()),
+				WriteDescriptorSet::buffer(11, scene_buffers.materials.buffer())
+			].into_iter().chain(Self::scene_writes(info_buffer, scene_buffers, heightmaps, volumes))
+		).unwrap()
+	}
+
+	/// The descriptors of the scene, shared by every shader that evaluates it (see shaders/scene_sdf.glsl)
+	fn scene_writes(
+		info_buffer: &Arc<CpuAccessibleBuffer<SceneInfo>>,
+		scene_buffers: &SceneBuffers,
+		heightmaps: &HeightmapArray,
+		volumes: &VolumeAtlas
+	) -> [WriteDescriptorSet; 8] {
+		[
+			WriteDescriptorSet::buffer(0, info_buffer.clone()),
+			WriteDescriptorSet::buffer(3, scene_buffers.shapes.buffer()),
+			WriteDescriptorSet::buffer(4, scene_buffers.instructions.buffer()),
+			WriteDescriptorSet::buffer(5, scene_buffers.palettes.buffer()),
+			WriteDescriptorSet::buffer(6, scene_buffers.fractal_ops.buffer()),
+			WriteDescriptorSet::buffer(7, scene_buffers.modifiers.buffer()),
+			WriteDescriptorSet::image_view_sampler(8, heightmaps.view(), heightmaps.sampler()),
+			WriteDescriptorSet::image_view_sampler(9, volumes.view(), volumes.sampler())
+		]
+	}
+
+	/// Rewrites the scene uniform and buffers in place. The current camera is kept unless the new scene's camera
+	/// differs from that of the previously set scene
+	pub fn set_scene(&mut self, scene: SceneDesc) -> Result<(), SceneError> {
+		self.set_scene_reloading(scene, &[])
+	}
+
+	/// Sets the scene, reloading the heightmaps and volumes loaded from `changed_files` even if they're still used
+	fn set_scene_reloading(&mut self, scene: SceneDesc, changed_files: &[PathBuf]) -> Result<(), SceneError> {
+		let mut flat = scene.flatten()?;
+		let lights = light::to_lights(&scene.lights)?;
+		let images_reloaded = self.load_images(&mut flat, changed_files)?;
+		let mut data = scene.to_scene_info(&flat);
+
+		{
+			let mut info = self._info_buffer.write().unwrap();
+			if scene.camera == self.scene.camera {
+				data.camera_pos = info.camera_pos;
+				data.look_at = info.look_at;
+				data.canvas_dist = info.canvas_dist;
+			}
+			data.output_mode = info.output_mode;
+			data.shadows = info.shadows;
+			*info = data;
+		}
+
+		self.scene = scene;
+		self.upload_scene(flat, images_reloaded);
+		self.upload_lights(lights);
+
+		Ok(())
+	}
+
+	/// Changes the seed and FBM settings shared by all the noise in the scene, without re-uploading its shapes
+	pub fn set_noise(&mut self, noise: NoiseSettings) -> Result<(), SceneError> {
+		noise.validate().map_err(SceneError::InvalidNoise)?;
+
+		{
+			let mut info = self._info_buffer.write().unwrap();
+			info.noise_seed = noise.seed;
+			info.noise_lacunarity = noise.lacunarity;
+			info.noise_gain = noise.gain;
 		}
+
+		self.scene.noise = noise;
+		Ok(())
+	}
+
+	/// Changes how creases and cavities are darkened, without re-uploading the scene's shapes
+	pub fn set_ambient_occlusion(&mut self, ambient_occlusion: AmbientOcclusion) -> Result<(), SceneError> {
+		ambient_occlusion.validate().map_err(SceneError::InvalidAmbientOcclusion)?;
+
+		{
+			let mut info = self._info_buffer.write().unwrap();
+			info.ao_samples = ambient_occlusion.samples;
+			info.ao_step = ambient_occlusion.step;
+			info.ao_strength = ambient_occlusion.strength;
+		}
+
+		self.scene.ambient_occlusion = ambient_occlusion;
+		Ok(())
+	}
+
+	pub fn output_mode(&self) -> OutputMode {
+		self.output_mode
+	}
+
+	/// Changes what's drawn, e.g. to inspect the ambient occlusion alone
+	pub fn set_output_mode(&mut self, output_mode: OutputMode) {
+		self._info_buffer.write().unwrap().output_mode = output_mode.to_u32();
+		self.output_mode = output_mode;
+	}
+
+	/// Turns shadows on or off for every light without re-uploading the scene, e.g. to move around faster. Lights
+	/// that don't cast shadows stay that way when they're turned on
+	pub fn set_shadows(&mut self, enabled: bool) {
+		self._info_buffer.write().unwrap().shadows = enabled as u32;
+		self.shadows = enabled;
+	}
+
+	pub fn shadows(&self) -> bool {
+		self.shadows
+	}
+
+	pub fn lights(&self) -> &[LightDesc] {
+		&self.scene.lights
+	}
+
+	/// Adds a light to the scene, returning its index
+	pub fn add_light(&mut self, light: LightDesc) -> Result<usize, SceneError> {
+		self.edit_lights(|lights| {
+			lights.push(light);
+			lights.len() - 1
+		})
+	}
+
+	/// Removes the light at `index`, shifting the lights after it down
+	pub fn remove_light(&mut self, index: usize) -> Result<LightDesc, SceneError> {
+		self.check_light_index(index)?;
+		self.edit_lights(|lights| lights.remove(index))
+	}
+
+	/// Replaces the light at `index`, returning the old light
+	pub fn replace_light(&mut self, index: usize, light: LightDesc) -> Result<LightDesc, SceneError> {
+		self.check_light_index(index)?;
+		self.edit_lights(|lights| std::mem::replace(&mut lights[index], light))
+	}
+
+	fn check_light_index(&self, index: usize) -> Result<(), SceneError> {
+		if index >= self.scene.lights.len() {
+			return Err(SceneError::NoSuchLight(index));
+		}
+		Ok(())
+	}
+
+	/// Applies `edit` to the scene's lights and uploads the result. If the edited lights are invalid then
+	/// the lights are left unchanged
+	fn edit_lights<R>(&mut self, edit: impl FnOnce(&mut Vec<LightDesc>) -> R) -> Result<R, SceneError> {
+		let mut lights = self.scene.lights.clone();
+		let result = edit(&mut lights);
+		let gpu_lights = light::to_lights(&lights)?;
+
+		self.scene.lights = lights;
+		self.upload_lights(gpu_lights);
+
+		Ok(result)
+	}
+
+	/// The scene's material table, which shapes reference by index
+	pub fn materials(&self) -> &[MaterialDesc] {
+		&self.scene.materials
+	}
+
+	/// Adds a material to the end of the material table, returning its index
+	pub fn add_material(&mut self, material: MaterialDesc) -> Result<usize, SceneError> {
+		self.edit_materials(|materials| {
+			materials.push(material);
+			materials.len() - 1
+		})
+	}
+
+	/// Removes the material at `index`, shifting the materials after it down. Fails if a shape still uses a material
+	/// past the end of the table. Panics if `index` is out of bounds
+	pub fn remove_material(&mut self, index: usize) -> Result<MaterialDesc, SceneError> {
+		self.edit_materials(|materials| materials.remove(index))
+	}
+
+	/// Replaces the material at `index`, returning the old material. Panics if `index` is out of bounds
+	pub fn replace_material(&mut self, index: usize, material: MaterialDesc) -> Result<MaterialDesc, SceneError> {
+		self.edit_materials(|materials| std::mem::replace(&mut materials[index], material))
+	}
+
+	/// Applies `edit` to the scene's material table and uploads the result. The shapes are laid out again, as they
+	/// take their albedo from their material. If the edited scene is invalid then the scene is left unchanged
+	fn edit_materials<R>(&mut self, edit: impl FnOnce(&mut Vec<MaterialDesc>) -> R) -> Result<R, SceneError> {
+		let mut materials = self.scene.materials.clone();
+		let result = edit(&mut materials);
+		let mut flat = FlatScene::flatten(&self.scene.shapes, &materials)?;
+		let images_reloaded = self.load_images(&mut flat, &[])?;
+
+		self.scene.materials = materials;
+		self.upload_scene(flat, images_reloaded);
+
+		Ok(result)
+	}
+
+	/// The shapes and groups at the top level of the scene
+	pub fn nodes(&self) -> &[CsgNode] {
+		&self.scene.shapes
+	}
+
+	/// Adds a shape or group to the end of the scene, returning its index
+	pub fn add_node<N: Into<CsgNode>>(&mut self, node: N) -> Result<usize, SceneError> {
+		self.edit_nodes(|nodes| {
+			nodes.push(node.into());
+			nodes.len() - 1
+		})
+	}
+
+	/// Removes the node at `index`, shifting the nodes after it down. Panics if `index` is out of bounds
+	pub fn remove_node(&mut self, index: usize) -> Result<CsgNode, SceneError> {
+		self.edit_nodes(|nodes| nodes.remove(index))
+	}
+
+	/// Replaces the node at `index`, returning the old node. Panics if `index` is out of bounds
+	pub fn replace_node<N: Into<CsgNode>>(&mut self, index: usize, node: N) -> Result<CsgNode, SceneError> {
+		self.edit_nodes(|nodes| std::mem::replace(&mut nodes[index], node.into()))
+	}
+
+	/// Applies `edit` to the top level nodes of the scene and uploads the result. If the edited scene
+	/// is invalid then the scene is left unchanged
+	fn edit_nodes<R>(&mut self, edit: impl FnOnce(&mut Vec<CsgNode>) -> R) -> Result<R, SceneError> {
+		let mut nodes = self.scene.shapes.clone();
+		let result = edit(&mut nodes);
+		let mut flat = FlatScene::flatten(&nodes, &self.scene.materials)?;
+		let images_reloaded = self.load_images(&mut flat, &[])?;
+
+		self.scene.shapes = nodes;
+		self.upload_scene(flat, images_reloaded);
+
+		Ok(result)
+	}
+
+	/// Loads the heightmaps and volumes the scene samples if they differ from those loaded or any of their files are in
+	/// `changed_files`, and fills in the shapes' parameters that depend on them. Returns true if any were reloaded, in which
+	/// case the descriptor set needs recreating. Nothing is replaced unless everything loads
+	fn load_images(&mut self, flat: &mut FlatScene, changed_files: &[PathBuf]) -> Result<bool, SceneError> {
+		let heightmaps = if flat.heightmaps != self.heightmaps.paths() || flat.heightmaps.iter().any(|path| changed_files.contains(path)) {
+			Some(HeightmapArray::load(&self.vk_target, &flat.heightmaps)?)
+		} else {
+			None
+		};
+		let volumes = if flat.volumes != self.volumes.sources() || flat.volumes.iter().any(|source| changed_files.iter().any(|path| path == source.path())) {
+			Some(VolumeAtlas::load(&self.vk_target, &flat.volumes)?)
+		} else {
+			None
+		};
+		let reload = heightmaps.is_some() || volumes.is_some();
+
+		if let Some(heightmaps) = heightmaps {
+			self.heightmaps = heightmaps;
+		}
+		if let Some(volumes) = volumes {
+			self.volumes = volumes;
+		}
+		self.heightmaps.set_step_scales(&mut flat.shapes);
+		self.volumes.set_volume_params(&mut flat.shapes);
+
+		Ok(reload)
+	}
+
+	fn upload_scene(&mut self, flat: FlatScene, images_reloaded: bool) {
+		{
+			let mut info = self._info_buffer.write().unwrap();
+			info.num_shapes = flat.shapes.len() as u32;
+			info.num_instructions = flat.instructions.len() as u32;
+		}
+
+		if self.scene_buffers.upload(flat) || images_reloaded {
+			self.descriptor_set = Self::create_descriptor_set(&self.compute_pipeline, &self._info_buffer, &self.image_view, &self.debug_buffer, &self.scene_buffers, &self.heightmaps, &self.volumes);
+		}
+	}
+
+	fn upload_lights(&mut self, lights: Vec<Light>) {
+		self._info_buffer.write().unwrap().num_lights = lights.len() as u32;
+
+		if self.scene_buffers.upload_lights(lights) {
+			self.descriptor_set = Self::create_descriptor_set(&self.compute_pipeline, &self._info_buffer, &self.image_view, &self.debug_buffer, &self.scene_buffers, &self.heightmaps, &self.volumes);
+		}
+	}
+
+	/// Reloads the scene file if it, or a heightmap, mesh or grid file it references, has changed since it was last
+	/// loaded. Returns None if the scene is not from a file or the files are unchanged. If the new scene fails to load,
+	/// the current one is kept
+	pub fn reload_scene_if_changed(&mut self) -> Option<Result<(), SceneError>> {
+		let (scene, changed_files) = self.scene_watcher.as_mut()?.poll()?;
+		Some(scene.and_then(|scene| self.set_scene_reloading(scene, &changed_files)))
 	}
 
 	pub fn render(&self) -> Arc<CpuAccessibleBuffer<[u8]>> {
@@ -204,4 +785,91 @@ impl Raymarch {
 
 		self.output_buffer.clone()
 	}
+
+	/// Samples the scene's distance function at the points of a grid over `region`, see [`GridRegion`]
+	pub fn sample_grid(&self, region: &GridRegion) -> Result<SdfGrid, ExportError> {
+		region.validate()?;
+		let (size, cell_size) = region.layout();
+
+		// Sampled a slab of z layers at a time, so the buffer read back through stays small at high resolutions
+		let layer_points = size[0] * size[1];
+		let slab_layers = export::slabs(size, MAX_GRID_SLAB_POINTS).map(|(_, layers)| layers).max().unwrap();
+		let slab_buffer = CpuAccessibleBuffer::from_iter(
+			self.vk_target.device.clone(),
+			BufferUsage { storage_buffer: true, ..Default::default() },
+			false,
+			(0..layer_points * slab_layers).map(|_| 0f32)
+		).expect("Failed to create buffer");
+
+		let layout = self.grid_pipeline.layout().set_layouts().get(0).unwrap();
+		let set = PersistentDescriptorSet::new(
+			layout.clone(),
+			[WriteDescriptorSet::buffer(1, slab_buffer.clone())].into_iter()
+				.chain(Self::scene_writes(&self._info_buffer, &self.scene_buffers, &self.heightmaps, &self.volumes))
+		).unwrap();
+
+		let mut distances = Vec::with_capacity((layer_points * size[2]) as usize);
+		for (first_layer, layers) in export::slabs(size, MAX_GRID_SLAB_POINTS) {
+			let grid_info = GridInfo {
+				origin: region.min,
+				cell_size,
+				size,
+				first_layer,
+				slab_layers: layers
+			};
+
+			let mut builder = AutoCommandBufferBuilder::primary(
+				self.vk_target.device.clone(),
+				self.vk_target.queue.queue_family_index(),
+				CommandBufferUsage::OneTimeSubmit
+			).unwrap();
+
+			builder
+				.bind_pipeline_compute(self.grid_pipeline.clone())
+				.bind_descriptor_sets(PipelineBindPoint::Compute,
+					self.grid_pipeline.layout().clone(),
+					0, set.clone()
+				)
+				.push_constants(self.grid_pipeline.layout().clone(), 0, grid_info)
+				.dispatch([(size[0] + 3) / 4, (size[1] + 3) / 4, (layers + 3) / 4])
+				.unwrap();
+
+			let command_buffer = builder.build().unwrap();
+
+			sync::now(self.vk_target.device.clone())
+				.then_execute(self.vk_target.queue.clone(), command_buffer)
+				.unwrap()
+				.then_signal_fence_and_flush()
+				.unwrap()
+				.wait(None)
+				.unwrap();
+
+			distances.extend_from_slice(&slab_buffer.read().unwrap()[..(layer_points * layers) as usize]);
+		}
+
+		Ok(SdfGrid::new(size, region.min, cell_size, distances))
+	}
+
+	/// Samples the scene's distance function over `region` and saves it to `path` as a grid file (see [`SdfGrid`]),
+	/// for a [`ShapeKind::Baked`] shape to render
+	pub fn bake<P: AsRef<Path>>(&self, path: P, region: &GridRegion) -> Result<(), ExportError> {
+		export::bake(self.sample_grid(region)?).save(path)?;
+		Ok(())
+	}
+
+	/// Extracts the scene's surface within `region` as a triangle mesh, and saves it to `path` as an OBJ or binary STL
+	/// file going by its extension. The mesh is closed, capped where the region's box cuts through the scene.
+	/// Sampling is slow at high resolutions, taking resolution³ evaluations of the scene
+	pub fn export_mesh<P: AsRef<Path>>(&self, path: P, region: &GridRegion) -> Result<(), ExportError> {
+		// Checked first so a bad path doesn't waste the sampling
+		MeshFormat::from_path(&path)?;
+
+		let mesh = export::contour(&self.sample_grid(region)?);
+		if mesh.triangles.is_empty() {
+			return Err(ExportError::Empty);
+		}
+		mesh.save(path)?;
+
+		Ok(())
+	}
 }
\ No newline at end of file
@@ -0,0 +1,397 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use super::{shaders::ray_marching_shader::ty::{Shape, CsgInstruction, Palette, FractalOp, Modifier, Material}, material::{MaterialDesc, to_materials}, shape::{ShapeDesc, ShapeKind, ShapeError, BlendMode, default_blend_strength, default_rotation, default_scale, normalise_transform}, scene::SceneError, domain::DomainOp, sdf_grid::VolumeSource, CSG_OP_PUSH_SHAPE, CSG_OP_COMBINE, SHAPE_TYPE_NONE};
+
+/// The size of the stack the shader evaluates the CSG instructions with
+pub const CSG_STACK_SIZE: usize = 16;
+
+/// The most transforms the shader will apply to a shape, i.e. the shape's own and those of its parents
+pub const MAX_TRANSFORM_DEPTH: usize = 8;
+
+/// A node in the tree of shapes making up a scene. Each node is combined with the nodes before it
+/// in the same group (or at the top level of the scene) by its blend mode, so e.g. (A ∪ B) − (C ∩ D) is
+/// ```ignore
+/// [
+///     Group(children: [Shape(A), Shape(B)]),
+///     Group(blend_mode: Difference, children: [Shape(C), Shape(D, blend_mode: Intersection)])
+/// ]
+/// ```
+// Shapes are most of the nodes in a scene, so boxing them would cost more than the space groups waste
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub enum CsgNode {
+	Shape(ShapeDesc),
+	Group(GroupDesc)
+}
+
+impl CsgNode {
+	fn blend(&self) -> (BlendMode, f32) {
+		match self {
+			CsgNode::Shape(shape) => (shape.blend_mode, shape.blend_strength),
+			CsgNode::Group(group) => (group.blend_mode, group.blend_strength)
+		}
+	}
+}
+
+impl From<ShapeDesc> for CsgNode {
+	fn from(shape: ShapeDesc) -> Self {
+		CsgNode::Shape(shape)
+	}
+}
+
+impl From<GroupDesc> for CsgNode {
+	fn from(group: GroupDesc) -> Self {
+		CsgNode::Group(group)
+	}
+}
+
+/// A group of nodes which are combined with each other first, then with the nodes before the group.
+/// The transforms of the nodes in the group are relative to the group's transform
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct GroupDesc {
+	pub children: Vec<CsgNode>,
+	#[serde(default)]
+	pub translation: [f32; 3],
+	/// Quaternion (x, y, z, w), normalised when the group is laid out
+	#[serde(default = "default_rotation")]
+	pub rotation: [f32; 4],
+	#[serde(default = "default_scale")]
+	pub scale: [f32; 3],
+	#[serde(default)]
+	pub blend_mode: BlendMode,
+	#[serde(default = "default_blend_strength")]
+	pub blend_strength: f32,
+	/// Repeats or mirrors the whole group, see [`DomainOp`]
+	#[serde(default)]
+	pub domain: Option<DomainOp>
+}
+
+impl GroupDesc {
+	pub fn new(children: Vec<CsgNode>) -> Self {
+		GroupDesc {
+			children,
+			translation: [0.; 3],
+			rotation: default_rotation(),
+			scale: default_scale(),
+			blend_mode: BlendMode::default(),
+			blend_strength: default_blend_strength(),
+			domain: None
+		}
+	}
+
+	pub fn translation(mut self, translation: [f32; 3]) -> Self {
+		self.translation = translation;
+		self
+	}
+
+	/// Sets the rotation as a quaternion (x, y, z, w), see [`super::axis_angle`]
+	pub fn rotation(mut self, rotation: [f32; 4]) -> Self {
+		self.rotation = rotation;
+		self
+	}
+
+	pub fn scale(mut self, scale: [f32; 3]) -> Self {
+		self.scale = scale;
+		self
+	}
+
+	/// Repeats or mirrors the whole group, see [`DomainOp`]
+	pub fn domain(mut self, domain: DomainOp) -> Self {
+		self.domain = Some(domain);
+		self
+	}
+
+	/// Sets how the group is combined with the nodes before it, see [`ShapeDesc::blend`]
+	pub fn blend(mut self, mode: BlendMode, strength: f32) -> Self {
+		self.blend_mode = mode;
+		self.blend_strength = strength;
+		self
+	}
+}
+
+/// A CSG tree laid out for the shader - The shapes, a postfix program that combines them, and the palettes, fractal
+/// ops, modifiers, materials, heightmaps and volumes the shapes reference. Groups are laid out as shapes of type SHAPE_TYPE_NONE that are never drawn, but hold the transform their children are relative to
+pub struct FlatScene {
+	pub shapes: Vec<Shape>,
+	pub instructions: Vec<CsgInstruction>,
+	pub palettes: Vec<Palette>,
+	pub fractal_ops: Vec<FractalOp>,
+	pub modifiers: Vec<Modifier>,
+	/// The scene's whole material table, in order, as shapes reference materials by index
+	pub materials: Vec<Material>,
+	/// The heightmap images to load, in order of their layer in the shader's heightmap array. Each is only listed once,
+	/// however many shapes use it
+	pub heightmaps: Vec<PathBuf>,
+	/// The signed distance grids to load, in order of their index in the shader's volume atlas. Each is only listed once,
+	/// however many shapes use it
+	pub volumes: Vec<VolumeSource>
+}
+
+impl FlatScene {
+	pub fn flatten(nodes: &[CsgNode], materials: &[MaterialDesc]) -> Result<Self, SceneError> {
+		let mut flattener = Flattener {
+			scene: FlatScene { shapes: Vec::new(), instructions: Vec::new(), palettes: Vec::new(), fractal_ops: Vec::new(), modifiers: Vec::new(), materials: to_materials(materials)?, heightmaps: Vec::new(), volumes: Vec::new() },
+			materials,
+			depth: 0,
+			ends: Vec::new(),
+			explicit_parents: Vec::new()
+		};
+		flattener.nodes(nodes, None)?;
+		flattener.resolve_parents()?;
+
+		Ok(flattener.scene)
+	}
+}
+
+struct Flattener<'a> {
+	scene: FlatScene,
+	materials: &'a [MaterialDesc],
+	/// The depth the shader's stack will be at after running the instructions so far
+	depth: usize,
+	/// The index after the last node laid out under each node so far, i.e. where its children end for groups
+	ends: Vec<usize>,
+	/// The shapes given an explicit parent, as (shape index, parent index, index of the group the shape is in)
+	explicit_parents: Vec<(usize, usize, Option<usize>)>
+}
+
+impl Flattener<'_> {
+	fn nodes(&mut self, nodes: &[CsgNode], parent: Option<usize>) -> Result<(), SceneError> {
+		for (i, node) in nodes.iter().enumerate() {
+			self.node(node, parent)?;
+
+			// The first node has nothing before it to be combined with
+			if i > 0 {
+				let (blend_mode, blend_strength) = node.blend();
+				self.push(CsgInstruction {
+					op: CSG_OP_COMBINE,
+					shape_index: 0,
+					blend_mode: blend_mode.id(),
+					blend_strength
+				})?;
+			}
+		}
+
+		Ok(())
+	}
+
+	fn node(&mut self, node: &CsgNode, parent: Option<usize>) -> Result<(), SceneError> {
+		let shape_index = self.scene.shapes.len();
+
+		match node {
+			CsgNode::Shape(shape) => {
+				let mut gpu_shape = shape.to_shape().map_err(|e| SceneError::Shape(shape_index, e))?;
+
+				// Explicit parents can come after the shape, so are filled in once everything is laid out
+				gpu_shape.parent = parent.map_or(-1, |p| p as i32);
+				if let Some(p) = shape.parent {
+					self.explicit_parents.push((shape_index, p, parent));
+				}
+
+				if let Some(material) = shape.material {
+					let desc = self.materials.get(material).ok_or(SceneError::UnknownMaterial(shape_index, material))?;
+					gpu_shape.material = material as i32;
+					gpu_shape.albedo = desc.albedo;
+				}
+				if let Some(palette) = &shape.palette {
+					gpu_shape.palette = self.scene.palettes.len() as i32;
+					self.scene.palettes.push(palette.to_palette().map_err(|e| SceneError::Shape(shape_index, ShapeError::Palette(shape.kind, e)))?);
+				}
+				if !shape.ops.is_empty() || !shape.schedule.is_empty() {
+					let first_op = self.scene.fractal_ops.len();
+					for (i, op) in shape.ops.iter().enumerate() {
+						self.scene.fractal_ops.push(op.to_fractal_op().map_err(|msg| SceneError::Shape(shape_index, ShapeError::InvalidOp(shape.kind, i, msg)))?);
+					}
+					for (i, step) in shape.schedule.iter().enumerate() {
+						self.scene.fractal_ops.extend(step.to_fractal_ops().map_err(|msg| SceneError::Shape(shape_index, ShapeError::InvalidStep(shape.kind, i, msg)))?);
+					}
+
+					// Laid out as (first op, op count) after the shape's own parameters
+					gpu_shape.params[1][0] = first_op as f32;
+					gpu_shape.params[1][1] = (self.scene.fractal_ops.len() - first_op) as f32;
+				}
+
+				if let Some(path) = &shape.heightmap {
+					let layer = self.scene.heightmaps.iter().position(|p| p == path).unwrap_or_else(|| {
+						self.scene.heightmaps.push(path.clone());
+						self.scene.heightmaps.len() - 1
+					});
+					// Laid out as (layer, step scale), with the step scale filled in once the heightmap is loaded
+					gpu_shape.params[0][0] = layer as f32;
+				}
+
+				let source = match (shape.kind, &shape.mesh, &shape.grid) {
+					(ShapeKind::Mesh { resolution }, Some(path), _) => Some(VolumeSource::Mesh { path: path.clone(), resolution }),
+					(ShapeKind::Baked, _, Some(path)) => Some(VolumeSource::Grid(path.clone())),
+					_ => None
+				};
+				if let Some(source) = source {
+					let index = self.scene.volumes.iter().position(|s| *s == source).unwrap_or_else(|| {
+						self.scene.volumes.push(source);
+						self.scene.volumes.len() - 1
+					});
+					// Replaced by where the grid is in the volume atlas once it's loaded
+					gpu_shape.params[0][0] = index as f32;
+				}
+
+				gpu_shape.first_modifier = self.scene.modifiers.len() as u32;
+				gpu_shape.num_modifiers = shape.modifiers.len() as u32;
+				for (i, modifier) in shape.modifiers.iter().enumerate() {
+					self.scene.modifiers.push(modifier.to_modifier(shape.bounding_radius()).map_err(|msg| SceneError::Shape(shape_index, ShapeError::InvalidModifier(shape.kind, i, msg)))?);
+				}
+
+				self.scene.shapes.push(gpu_shape);
+				self.ends.push(shape_index + 1);
+				self.push(CsgInstruction {
+					op: CSG_OP_PUSH_SHAPE,
+					shape_index: shape_index as u32,
+					blend_mode: 0,
+					blend_strength: 0.
+				})
+			},
+			CsgNode::Group(group) => {
+				if group.children.is_empty() {
+					return Err(SceneError::EmptyGroup);
+				}
+				if !group.translation.iter().chain(&group.rotation).chain(&group.scale).chain([&group.blend_strength]).all(|v| v.is_finite()) {
+					return Err(SceneError::NonFiniteGroup);
+				}
+				if group.blend_mode == BlendMode::Smooth && group.blend_strength <= 0. {
+					return Err(SceneError::NonPositiveBlendStrength(group.blend_strength));
+				}
+				let rotation = normalise_transform(group.rotation, group.scale).ok_or(SceneError::InvalidGroupTransform)?;
+				if let Some(domain) = &group.domain {
+					domain.validate().map_err(SceneError::InvalidGroupDomain)?;
+				}
+				let (domain_op, domain_params) = DomainOp::to_gpu(group.domain);
+
+				self.scene.shapes.push(Shape {
+					position: group.translation,
+					shape_type: SHAPE_TYPE_NONE,
+					rotation,
+					scale: group.scale,
+					parent: parent.map_or(-1, |p| p as i32),
+					domain_op,
+					domain_params,
+					..Default::default()
+				});
+				self.ends.push(shape_index + 1);
+
+				self.nodes(&group.children, Some(shape_index))?;
+				self.ends[shape_index] = self.scene.shapes.len();
+				Ok(())
+			}
+		}
+	}
+
+	/// Points the shapes with explicit parents at them, then checks no shape has more transforms applying to it than
+	/// the shader supports. A shape in a group can only have a parent in the same group (or the group itself), so the
+	/// chain of parents always leads back through the group and its transform still applies
+	fn resolve_parents(&mut self) -> Result<(), SceneError> {
+		let shapes = &mut self.scene.shapes;
+		for &(shape_index, p, group) in &self.explicit_parents {
+			let scope = group.map_or(0..shapes.len(), |g| g..self.ends[g]);
+			if !scope.contains(&p) {
+				return Err(SceneError::InvalidParent(shape_index, p));
+			}
+			shapes[shape_index].parent = p as i32;
+		}
+
+		for start in 0..shapes.len() {
+			let mut index = start as i32;
+			let mut depth = 0;
+			while index >= 0 {
+				depth += 1;
+				// Without a cycle the chain can't be longer than the number of shapes
+				if depth > shapes.len() {
+					return Err(SceneError::ParentCycle(start));
+				}
+				index = shapes[index as usize].parent;
+			}
+			if depth > MAX_TRANSFORM_DEPTH {
+				return Err(SceneError::TransformTooDeep);
+			}
+		}
+
+		Ok(())
+	}
+
+	fn push(&mut self, instruction: CsgInstruction) -> Result<(), SceneError> {
+		if instruction.op == CSG_OP_PUSH_SHAPE {
+			self.depth += 1;
+			if self.depth > CSG_STACK_SIZE {
+				return Err(SceneError::CsgTooDeep);
+			}
+		} else {
+			self.depth -= 1;
+		}
+
+		self.scene.instructions.push(instruction);
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{*, super::SHAPE_TYPE_SPHERE};
+
+	fn sphere() -> ShapeDesc {
+		ShapeDesc::new(ShapeKind::Sphere)
+	}
+
+	/// A group holding a sphere and a group nested `levels` deep, which leaves the sphere on the shader's stack
+	/// while the nested group is evaluated
+	fn nested(levels: usize) -> CsgNode {
+		match levels {
+			0 => sphere().into(),
+			_ => GroupDesc::new(vec![sphere().into(), nested(levels - 1)]).into()
+		}
+	}
+
+	#[test]
+	fn groups_are_laid_out_before_their_children() {
+		let flat = FlatScene::flatten(&[sphere().into(), nested(1)], &[]).unwrap();
+		assert_eq!(flat.shapes.iter().map(|s| s.shape_type).collect::<Vec<_>>(), [SHAPE_TYPE_SPHERE, SHAPE_TYPE_NONE, SHAPE_TYPE_SPHERE, SHAPE_TYPE_SPHERE]);
+		assert_eq!(flat.shapes.iter().map(|s| s.parent).collect::<Vec<_>>(), [-1, -1, 1, 1]);
+		let ops = flat.instructions.iter().map(|i| i.op).collect::<Vec<_>>();
+		assert_eq!(ops, [CSG_OP_PUSH_SHAPE, CSG_OP_PUSH_SHAPE, CSG_OP_PUSH_SHAPE, CSG_OP_COMBINE, CSG_OP_COMBINE]);
+	}
+
+	#[test]
+	fn csg_trees_needing_too_big_a_stack_are_rejected() {
+		assert!(FlatScene::flatten(&[nested(4)], &[]).is_ok());
+		assert!(matches!(FlatScene::flatten(&[nested(CSG_STACK_SIZE)], &[]), Err(SceneError::CsgTooDeep)));
+	}
+
+	#[test]
+	fn explicit_parents_can_come_after_the_shape() {
+		let flat = FlatScene::flatten(&[sphere().parent(1).into(), sphere().into()], &[]).unwrap();
+		assert_eq!(flat.shapes[0].parent, 1);
+		assert_eq!(flat.shapes[1].parent, -1);
+	}
+
+	#[test]
+	fn parents_must_be_in_the_scene_and_the_shapes_group() {
+		assert!(matches!(FlatScene::flatten(&[sphere().parent(1).into()], &[]), Err(SceneError::InvalidParent(0, 1))));
+
+		// The group is at index 1 and its children at 2 and 3
+		let group = |parent| GroupDesc::new(vec![sphere().into(), sphere().parent(parent).into()]);
+		assert!(FlatScene::flatten(&[sphere().into(), group(1).into()], &[]).is_ok());
+		assert!(FlatScene::flatten(&[sphere().into(), group(2).into()], &[]).is_ok());
+		assert!(matches!(FlatScene::flatten(&[sphere().into(), group(0).into()], &[]), Err(SceneError::InvalidParent(3, 0))));
+	}
+
+	#[test]
+	fn parents_cant_loop() {
+		assert!(matches!(FlatScene::flatten(&[sphere().parent(0).into()], &[]), Err(SceneError::ParentCycle(0))));
+		assert!(matches!(FlatScene::flatten(&[sphere().parent(1).into(), sphere().parent(0).into()], &[]), Err(SceneError::ParentCycle(0))));
+	}
+
+	#[test]
+	fn parent_chains_are_limited() {
+		let chain = |len: usize| (0..len).map(|i| if i == 0 { sphere() } else { sphere().parent(i - 1) }.into()).collect::<Vec<CsgNode>>();
+		assert!(FlatScene::flatten(&chain(MAX_TRANSFORM_DEPTH), &[]).is_ok());
+		assert!(matches!(FlatScene::flatten(&chain(MAX_TRANSFORM_DEPTH + 1), &[]), Err(SceneError::TransformTooDeep)));
+	}
+}
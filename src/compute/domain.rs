@@ -0,0 +1,67 @@
+use serde::Deserialize;
+
+use super::{DOMAIN_OP_NONE, DOMAIN_OP_REPEAT, DOMAIN_OP_REPEAT_LIMITED, DOMAIN_OP_MIRROR, DOMAIN_OP_POLAR};
+
+/// Folds space before a shape or group is evaluated, so it's drawn many times for the cost of one. Applied in
+/// the node's local space, after its transform. Repeated copies should fit within their cells, or the distances
+/// across cell boundaries will be overestimated
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum DomainOp {
+	/// Repeats forever, every `spacing` along each axis. A spacing of 0 doesn't repeat along that axis
+	Repeat {
+		spacing: [f32; 3]
+	},
+	/// Repeats every `spacing` along each axis, with `limit` copies either side of the original
+	RepeatLimited {
+		spacing: [f32; 3],
+		limit: [u32; 3]
+	},
+	/// Mirrors the positive side of each of the given axes (x, y, z) onto the negative side. The copies are
+	/// moved `offset` away from the mirror along each mirrored axis, so a single shape becomes several
+	Mirror {
+		axes: [bool; 3],
+		#[serde(default)]
+		offset: [f32; 3]
+	},
+	/// Repeats `count` times around the y axis, starting from the x axis. The copies are moved `radius`
+	/// away from the axis, so a single shape becomes a ring
+	Polar {
+		count: u32,
+		#[serde(default)]
+		radius: f32
+	}
+}
+
+impl DomainOp {
+	/// Checks the operation, returning why it's invalid if it is
+	pub fn validate(&self) -> Result<(), &'static str> {
+		match *self {
+			DomainOp::Repeat { spacing } | DomainOp::RepeatLimited { spacing, .. } if !spacing.iter().all(|s| s.is_finite() && *s >= 0.) => {
+				Err("spacing must be finite and not negative")
+			},
+			DomainOp::Repeat { spacing } | DomainOp::RepeatLimited { spacing, .. } if spacing == [0.; 3] => {
+				Err("spacing must be positive on at least one axis")
+			},
+			DomainOp::Mirror { offset, .. } if !offset.iter().all(|o| o.is_finite()) => Err("mirror offset must be finite"),
+			DomainOp::Polar { count: 0, .. } => Err("polar repetition count must be at least 1"),
+			DomainOp::Polar { radius, .. } if !radius.is_finite() => Err("polar repetition radius must be finite"),
+			_ => Ok(())
+		}
+	}
+
+	/// Lays the operation out as the shader's (Shape::domain_op, Shape::domain_params)
+	pub(super) fn to_gpu(op: Option<Self>) -> (u32, [[f32; 4]; 2]) {
+		match op {
+			None => (DOMAIN_OP_NONE, [[0.; 4]; 2]),
+			Some(DomainOp::Repeat { spacing }) => (DOMAIN_OP_REPEAT, [[spacing[0], spacing[1], spacing[2], 0.], [0.; 4]]),
+			Some(DomainOp::RepeatLimited { spacing, limit }) => {
+				(DOMAIN_OP_REPEAT_LIMITED, [[spacing[0], spacing[1], spacing[2], 0.], [limit[0] as f32, limit[1] as f32, limit[2] as f32, 0.]])
+			},
+			Some(DomainOp::Mirror { axes, offset }) => {
+				let axes = axes.map(|a| if a { 1. } else { 0. });
+				(DOMAIN_OP_MIRROR, [[axes[0], axes[1], axes[2], 0.], [offset[0], offset[1], offset[2], 0.]])
+			},
+			Some(DomainOp::Polar { count, radius }) => (DOMAIN_OP_POLAR, [[count as f32, radius, 0., 0.], [0.; 4]])
+		}
+	}
+}
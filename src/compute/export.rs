@@ -0,0 +1,295 @@
+use std::fmt;
+
+use super::{mesh::{Mesh, MeshError}, sdf_grid::{SdfGrid, GridError}};
+
+/// The fewest and most points along the longest side of a region the scene is sampled in
+pub const MIN_GRID_RESOLUTION: u32 = 8;
+pub const MAX_GRID_RESOLUTION: u32 = 512;
+
+#[derive(Debug)]
+pub enum ExportError {
+	/// The region to sample the scene in is invalid, with a message saying why
+	InvalidRegion(&'static str),
+	/// The region's resolution is out of range, with the resolution it has
+	InvalidResolution(u32),
+	/// The scene has no surface within the region
+	Empty,
+	/// The mesh failed to save
+	Mesh(MeshError),
+	/// The baked grid failed to save
+	Grid(GridError)
+}
+
+impl fmt::Display for ExportError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ExportError::InvalidRegion(msg) => write!(f, "Invalid export region: {}", msg),
+			ExportError::InvalidResolution(resolution) => write!(f, "Export resolution must be between {} and {}, but is {}", MIN_GRID_RESOLUTION, MAX_GRID_RESOLUTION, resolution),
+			ExportError::Empty => write!(f, "The scene has no surface within the export region"),
+			ExportError::Mesh(e) => write!(f, "Failed to save mesh: {}", e),
+			ExportError::Grid(e) => write!(f, "Failed to save grid: {}", e)
+		}
+	}
+}
+
+impl std::error::Error for ExportError {}
+
+impl From<MeshError> for ExportError {
+	fn from(e: MeshError) -> Self {
+		ExportError::Mesh(e)
+	}
+}
+
+impl From<GridError> for ExportError {
+	fn from(e: GridError) -> Self {
+		ExportError::Grid(e)
+	}
+}
+
+/// A box to sample the scene's distance function in, with `resolution` points along its longest side. The points
+/// are spaced evenly along each axis from `min`, reaching `max` or just past it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GridRegion {
+	pub min: [f32; 3],
+	pub max: [f32; 3],
+	pub resolution: u32
+}
+
+impl GridRegion {
+	pub fn new(min: [f32; 3], max: [f32; 3], resolution: u32) -> Self {
+		GridRegion { min, max, resolution }
+	}
+
+	/// Checks the region, returning why it's invalid if it is
+	pub fn validate(&self) -> Result<(), ExportError> {
+		if !self.min.iter().chain(&self.max).all(|v| v.is_finite()) {
+			return Err(ExportError::InvalidRegion("corners must be finite"));
+		}
+		if (0..3).any(|i| self.max[i] <= self.min[i]) {
+			return Err(ExportError::InvalidRegion("max must be greater than min on every axis"));
+		}
+		if !(MIN_GRID_RESOLUTION..=MAX_GRID_RESOLUTION).contains(&self.resolution) {
+			return Err(ExportError::InvalidResolution(self.resolution));
+		}
+
+		Ok(())
+	}
+
+	/// The number of points along each axis, and the distance between them
+	pub fn layout(&self) -> ([u32; 3], f32) {
+		let extents = [0, 1, 2].map(|i| self.max[i] - self.min[i]);
+		let cell_size = extents.iter().fold(0f32, |a, &b| a.max(b)) / (self.resolution - 1) as f32;
+
+		// Rounding error shouldn't add a point to the longest side
+		let size = extents.map(|e| ((e / cell_size - 1e-3).ceil() as u32 + 1).max(2));
+		(size, cell_size)
+	}
+}
+
+/// Splits the z layers of a grid with `size` points along each axis into slabs of at most `max_points` points,
+/// though always at least one layer, as (first layer, number of layers). Every slab but the last is the same size
+pub fn slabs(size: [u32; 3], max_points: u32) -> impl Iterator<Item = (u32, u32)> {
+	let slab_layers = (max_points / (size[0] * size[1])).clamp(1, size[2]);
+	(0..size[2]).step_by(slab_layers as usize).map(move |first_layer| (first_layer, slab_layers.min(size[2] - first_layer)))
+}
+
+/// Prepares sampled distances for saving as a grid file. Distances beyond the grid's diagonal are clamped to it, as
+/// they're still lower bounds but tell a shape rendering the grid nothing more, and so the infinite distances of an
+/// empty scene can be interpolated
+pub fn bake(grid: SdfGrid) -> SdfGrid {
+	let diagonal = grid.size().map(|s| (s - 1) as f32 * grid.cell_size()).iter().map(|e| e * e).sum::<f32>().sqrt();
+	let distances = grid.distances().iter().map(|&d| if d.is_nan() { diagonal } else { d.clamp(-diagonal, diagonal) }).collect();
+
+	SdfGrid::new(grid.size(), grid.origin(), grid.cell_size(), distances)
+}
+
+/// Extracts the surface where the grid's distances cross zero as a closed triangle mesh. Uses surface nets, a
+/// simplified dual contouring: each cell the surface passes through gets a vertex at the mean of where the surface
+/// crosses its edges, and the vertices of the 4 cells around each crossed edge are joined into a quad. Points on
+/// the sides of the grid are treated as outside, so the mesh is capped where the grid cuts through the surface
+pub fn contour(grid: &SdfGrid) -> Mesh {
+	let size = grid.size();
+	let cell_size = grid.cell_size();
+	let origin = grid.origin();
+	let point_index = |p: [u32; 3]| (p[0] + size[0] * (p[1] + size[1] * p[2])) as usize;
+	let distance = |p: [u32; 3]| {
+		let on_side = (0..3).any(|i| p[i] == 0 || p[i] == size[i] - 1);
+		let d = grid.distances()[point_index(p)];
+		if on_side { d.max(0.) } else { d }
+	};
+	let is_inside = |p: [u32; 3]| distance(p) < 0.;
+
+	// The vertex in each cell the surface passes through, indexed like the points at the cells' lowest corners
+	let mut cell_vertices = vec![None; grid.distances().len()];
+	let mut vertices = Vec::new();
+	for z in 0..size[2] - 1 {
+		for y in 0..size[1] - 1 {
+			for x in 0..size[0] - 1 {
+				let corners = [0, 1, 2, 3, 4, 5, 6, 7].map(|c| [x + (c & 1), y + ((c >> 1) & 1), z + ((c >> 2) & 1)]);
+				let distances = corners.map(distance);
+
+				let mut sum = [0.; 3];
+				let mut crossings = 0;
+				for (a, b) in CELL_EDGES {
+					let (da, db) = (distances[a], distances[b]);
+					if (da < 0.) != (db < 0.) {
+						let t = da / (da - db);
+						for (i, s) in sum.iter_mut().enumerate() {
+							*s += corners[a][i] as f32 + t * (corners[b][i] as f32 - corners[a][i] as f32);
+						}
+						crossings += 1;
+					}
+				}
+
+				if crossings > 0 {
+					cell_vertices[point_index([x, y, z])] = Some(vertices.len());
+					vertices.push([0, 1, 2].map(|i| origin[i] + sum[i] / crossings as f32 * cell_size));
+				}
+			}
+		}
+	}
+
+	// Edges on the sides of the grid never cross the surface, so every crossed edge has 4 cells around it
+	let mut triangles = Vec::new();
+	for z in 0..size[2] {
+		for y in 0..size[1] {
+			for x in 0..size[0] {
+				let p = [x, y, z];
+				for axis in 0..3 {
+					let mut q = p;
+					q[axis] += 1;
+					if q[axis] >= size[axis] || is_inside(p) == is_inside(q) {
+						continue;
+					}
+
+					// The cells around the edge, counter-clockwise seen from the end of the edge along its axis
+					let (b, c) = ((axis + 1) % 3, (axis + 2) % 3);
+					let around = [[0, 0], [1, 0], [1, 1], [0, 1]].map(|[db, dc]| {
+						let mut cell = p;
+						cell[b] -= db;
+						cell[c] -= dc;
+						vertices[cell_vertices[point_index(cell)].expect("Cell around a crossed edge has no vertex")]
+					});
+
+					// Facing out of the surface, towards the outside end of the edge
+					let [v0, v1, v2, v3] = if is_inside(p) { around } else { [around[3], around[2], around[1], around[0]] };
+					triangles.push([v0, v1, v2]);
+					triangles.push([v0, v2, v3]);
+				}
+			}
+		}
+	}
+
+	Mesh { triangles }
+}
+
+/// The 12 edges of a cell, as pairs of corners. Corner i is offset by (i & 1, (i >> 1) & 1, (i >> 2) & 1)
+const CELL_EDGES: [(usize, usize); 12] = [
+	(0, 1), (2, 3), (4, 5), (6, 7),
+	(0, 2), (1, 3), (4, 6), (5, 7),
+	(0, 4), (1, 5), (2, 6), (3, 7)
+];
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+
+	use super::*;
+
+	/// A grid of the distances to a sphere of radius 1 at the origin, sampled in `region`
+	fn sphere_grid(region: &GridRegion) -> SdfGrid {
+		let (size, cell_size) = region.layout();
+		let mut distances = Vec::new();
+		for z in 0..size[2] {
+			for y in 0..size[1] {
+				for x in 0..size[0] {
+					let p = [x, y, z].map(|c| c as f32 * cell_size);
+					distances.push((0..3).map(|i| (region.min[i] + p[i]).powi(2)).sum::<f32>().sqrt() - 1.);
+				}
+			}
+		}
+
+		SdfGrid::new(size, region.min, cell_size, distances)
+	}
+
+	/// The sphere contoured in a region around it with room to spare
+	fn contoured_sphere() -> Mesh {
+		contour(&sphere_grid(&GridRegion::new([-1.5; 3], [1.5; 3], 32)))
+	}
+
+	/// Whether every edge of the mesh is shared by exactly two triangles, which wind along it in opposite directions
+	fn is_closed(mesh: &Mesh) -> bool {
+		let mut edges = HashMap::new();
+		for triangle in &mesh.triangles {
+			for i in 0..3 {
+				let (a, b) = (triangle[i].map(f32::to_bits), triangle[(i + 1) % 3].map(f32::to_bits));
+				*edges.entry((a, b)).or_insert(0) += 1;
+				*edges.entry((b, a)).or_insert(0) -= 1;
+			}
+		}
+
+		edges.values().all(|&count| count == 0)
+	}
+
+	#[test]
+	fn layout_has_resolution_points_along_the_longest_side() {
+		assert_eq!(GridRegion::new([0.; 3], [2., 1., 0.5], 9).layout(), ([9, 5, 3], 0.25));
+
+		// Sides that aren't a whole number of cells long are rounded up to cover the region
+		let (size, cell_size) = GridRegion::new([0.; 3], [1., 0.3, 0.01], 11).layout();
+		assert_eq!(size, [11, 4, 2]);
+		assert!((cell_size - 0.1).abs() < 1e-6);
+
+		// Rounding error in the cell size mustn't add a point to the longest side
+		for resolution in MIN_GRID_RESOLUTION..=MAX_GRID_RESOLUTION {
+			for extent in [0.1, 0.3, 1., 3.7, 10.] {
+				let (size, _) = GridRegion::new([-extent; 3], [extent, extent * 0.7, extent], resolution).layout();
+				assert_eq!((size[0], size[2]), (resolution, resolution), "resolution {} over {}", resolution, extent);
+			}
+		}
+	}
+
+	#[test]
+	fn slabs_cover_every_layer_once() {
+		for (size, max_points) in [([4, 4, 10], 48), ([4, 4, 10], 16), ([4, 4, 10], 15), ([4, 4, 10], 1000), ([3, 5, 7], 45)] {
+			let slabs: Vec<_> = slabs(size, max_points).collect();
+
+			let mut next_layer = 0;
+			for &(first_layer, layers) in &slabs {
+				assert_eq!(first_layer, next_layer, "slabs {:?} of {:?}", slabs, size);
+				assert!(layers >= 1 && (layers == 1 || layers * size[0] * size[1] <= max_points), "slabs {:?} of {:?}", slabs, size);
+				next_layer += layers;
+			}
+			assert_eq!(next_layer, size[2], "slabs {:?} of {:?}", slabs, size);
+		}
+
+		assert_eq!(slabs([4, 4, 10], 48).collect::<Vec<_>>(), vec![(0, 3), (3, 3), (6, 3), (9, 1)]);
+	}
+
+	#[test]
+	fn contoured_sphere_is_closed() {
+		let mesh = contoured_sphere();
+
+		assert!(!mesh.triangles.is_empty());
+		assert!(is_closed(&mesh));
+	}
+
+	#[test]
+	fn contoured_sphere_faces_out() {
+		let mesh = contoured_sphere();
+
+		// Positive if the triangles wind counter-clockwise seen from outside
+		let volume: f32 = mesh.triangles.iter().map(|[a, b, c]| {
+			a[0] * (b[1] * c[2] - b[2] * c[1]) - a[1] * (b[0] * c[2] - b[2] * c[0]) + a[2] * (b[0] * c[1] - b[1] * c[0])
+		}).sum::<f32>() / 6.;
+		let sphere_volume = 4. / 3. * std::f32::consts::PI;
+		assert!((volume - sphere_volume).abs() < 0.05 * sphere_volume, "volume is {}, not {}", volume, sphere_volume);
+	}
+
+	#[test]
+	fn sphere_cut_by_the_grid_is_capped() {
+		let mesh = contour(&sphere_grid(&GridRegion::new([-1., -1.5, -0.5], [1.5, 1.5, 1.5], 24)));
+
+		assert!(is_closed(&mesh));
+	}
+}
@@ -0,0 +1,157 @@
+use serde::Deserialize;
+
+use super::{shaders::ray_marching_shader::ty::FractalOp, shape::{normalise, normalise_transform}, FRACTAL_OP_BOX_FOLD, FRACTAL_OP_SPHERE_FOLD, FRACTAL_OP_PLANE_REFLECTION, FRACTAL_OP_ROTATION, FRACTAL_OP_SCALE, FRACTAL_OP_OFFSET, FRACTAL_OP_MANDELBULB, FRACTAL_OP_MANDELBOX};
+
+/// The most operations a shape can run per iteration, to keep the shader from stalling
+pub const MAX_FRACTAL_OPS: usize = 32;
+
+/// An operation applied to the point being iterated by a [`super::ShapeKind::Kifs`] shape. The shape
+/// runs its operations in order each iteration
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum FractalOpDesc {
+	/// Reflects each coordinate outside -limit - limit back inside it
+	BoxFold {
+		#[serde(default = "default_fold_limit")]
+		limit: f32
+	},
+	/// Scales up points within min_radius of the centre by a fixed amount, and inverts points
+	/// between that and fixed_radius through the fixed_radius sphere
+	SphereFold {
+		min_radius: f32,
+		fixed_radius: f32
+	},
+	/// Reflects points behind the plane to in front of it. The plane faces along normal, at
+	/// distance from the centre
+	PlaneReflection {
+		normal: [f32; 3],
+		#[serde(default)]
+		distance: f32
+	},
+	/// Rotates by the quaternion (x, y, z, w), see [`super::axis_angle`]
+	Rotation([f32; 4]),
+	/// Scales about the centre. Usually above 1, so the fractal gets more detailed each iteration
+	Scale(f32),
+	Offset([f32; 3])
+}
+
+fn default_fold_limit() -> f32 {
+	1.
+}
+
+impl FractalOpDesc {
+	/// Checks the operation, returning why it's invalid if it is
+	pub fn validate(&self) -> Result<(), &'static str> {
+		let values: &[f32] = match self {
+			FractalOpDesc::BoxFold { limit } => &[*limit],
+			FractalOpDesc::SphereFold { min_radius, fixed_radius } => &[*min_radius, *fixed_radius],
+			FractalOpDesc::PlaneReflection { normal, distance } => &[normal[0], normal[1], normal[2], *distance],
+			FractalOpDesc::Rotation(rotation) => rotation,
+			FractalOpDesc::Scale(scale) => &[*scale],
+			FractalOpDesc::Offset(offset) => offset
+		};
+		if !values.iter().all(|v| v.is_finite()) {
+			return Err("values must be finite");
+		}
+
+		match *self {
+			FractalOpDesc::BoxFold { limit } if limit <= 0. => Err("box fold limit must be positive"),
+			FractalOpDesc::SphereFold { min_radius, fixed_radius } if min_radius <= 0. || fixed_radius < min_radius => {
+				Err("sphere fold min radius must be positive and no greater than fixed radius")
+			},
+			FractalOpDesc::PlaneReflection { normal, .. } if normalise(normal).is_none() => Err("plane reflection normal must be finite and non-zero"),
+			FractalOpDesc::Rotation(rotation) if normalise_transform(rotation, [1.; 3]).is_none() => Err("rotation must be finite and non-zero"),
+			FractalOpDesc::Scale(scale) if scale.abs() < f32::EPSILON => Err("scale must not be zero"),
+			_ => Ok(())
+		}
+	}
+
+	/// Validates the operation and lays it out as expected by the shader's fractal op buffer
+	pub fn to_fractal_op(self) -> Result<FractalOp, &'static str> {
+		self.validate()?;
+
+		let (op, params) = match self {
+			FractalOpDesc::BoxFold { limit } => (FRACTAL_OP_BOX_FOLD, [limit, 0., 0., 0.]),
+			FractalOpDesc::SphereFold { min_radius, fixed_radius } => (FRACTAL_OP_SPHERE_FOLD, [min_radius, fixed_radius, 0., 0.]),
+			FractalOpDesc::PlaneReflection { normal, distance } => {
+				let [x, y, z] = normalise(normal).unwrap();
+				(FRACTAL_OP_PLANE_REFLECTION, [x, y, z, distance])
+			},
+			FractalOpDesc::Rotation(rotation) => (FRACTAL_OP_ROTATION, normalise_transform(rotation, [1.; 3]).unwrap()),
+			FractalOpDesc::Scale(scale) => (FRACTAL_OP_SCALE, [scale, 0., 0., 0.]),
+			FractalOpDesc::Offset(offset) => (FRACTAL_OP_OFFSET, [offset[0], offset[1], offset[2], 0.])
+		};
+
+		Ok(FractalOp {
+			params,
+			op,
+			_dummy0: [0; 12],
+		})
+	}
+}
+
+/// An escape time formula a [`super::ShapeKind::Hybrid`] shape can run for some of its iterations. Each adds the
+/// starting point back on at the end, like the shape types of the same name
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum HybridFormula {
+	Mandelbulb {
+		power: f32
+	},
+	Mandelbox {
+		scale: f32,
+		min_radius: f32,
+		fixed_radius: f32
+	}
+}
+
+/// Runs a formula for `count` iterations in a row, see [`super::ShapeDesc::schedule`]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct HybridStep {
+	pub formula: HybridFormula,
+	#[serde(default = "default_step_count")]
+	pub count: u32
+}
+
+fn default_step_count() -> u32 {
+	1
+}
+
+impl HybridStep {
+	pub fn new(formula: HybridFormula, count: u32) -> Self {
+		HybridStep { formula, count }
+	}
+
+	/// Checks the step, returning why it's invalid if it is
+	pub fn validate(&self) -> Result<(), &'static str> {
+		if self.count == 0 {
+			return Err("count must be at least 1");
+		}
+
+		match self.formula {
+			HybridFormula::Mandelbulb { power } if !power.is_finite() || power < 2. => Err("power must be at least 2"),
+			HybridFormula::Mandelbox { scale, min_radius, fixed_radius } if !(scale.is_finite() && min_radius.is_finite() && fixed_radius.is_finite()) => {
+				Err("values must be finite")
+			},
+			HybridFormula::Mandelbox { scale, .. } if scale.abs() < 1. => Err("scale must be at least 1 or at most -1"),
+			HybridFormula::Mandelbox { min_radius, fixed_radius, .. } if min_radius <= 0. || fixed_radius < min_radius => {
+				Err("min radius must be positive and no greater than fixed radius")
+			},
+			_ => Ok(())
+		}
+	}
+
+	/// Validates the step and lays it out as expected by the shader's fractal op buffer, as one op per iteration
+	pub fn to_fractal_ops(self) -> Result<impl Iterator<Item = FractalOp>, &'static str> {
+		self.validate()?;
+
+		let (op, params) = match self.formula {
+			HybridFormula::Mandelbulb { power } => (FRACTAL_OP_MANDELBULB, [power, 0., 0., 0.]),
+			HybridFormula::Mandelbox { scale, min_radius, fixed_radius } => (FRACTAL_OP_MANDELBOX, [scale, min_radius, fixed_radius, 0.])
+		};
+
+		Ok((0..self.count).map(move |_| FractalOp {
+			params,
+			op,
+			_dummy0: [0; 12],
+		}))
+	}
+}
@@ -0,0 +1,132 @@
+use std::{sync::Arc, path::PathBuf};
+
+use image::{ImageBuffer, Luma, imageops::{self, FilterType}};
+
+use vulkano::{image::{ImmutableImage, ImageDimensions, MipmapsCount, view::{ImageView, ImageViewCreateInfo, ImageViewType}}, format::Format, sampler::{Sampler, SamplerCreateInfo, SamplerAddressMode, Filter}, sync::GpuFuture};
+
+use crate::vulkan_computil::VkTarget;
+
+use super::{shaders::ray_marching_shader::ty::Shape, scene::SceneError, SHAPE_TYPE_HEIGHTMAP};
+
+type HeightImage = ImageBuffer<Luma<u16>, Vec<u16>>;
+
+/// The heightmaps a scene's Heightmap shapes sample, loaded as the layers of an image array. Heightmaps smaller
+/// than the largest are stretched to its size, so every layer is the same size
+pub struct HeightmapArray {
+	paths: Vec<PathBuf>,
+	/// The most the height (from 0 to 1) changes between neighbouring texels along x and y, for each layer
+	slopes: Vec<[f32; 2]>,
+	width: u32,
+	height: u32,
+	view: Arc<ImageView<ImmutableImage>>,
+	sampler: Arc<Sampler>
+}
+
+impl HeightmapArray {
+	/// Loads the grayscale images at `paths` and uploads them, in order. With no paths a single flat layer is
+	/// uploaded, as the shader always expects something bound
+	pub fn load(vk_target: &VkTarget, paths: &[PathBuf]) -> Result<Self, SceneError> {
+		let properties = vk_target.device.physical_device().properties();
+		if paths.len() > properties.max_image_array_layers as usize {
+			return Err(SceneError::TooManyHeightmaps(properties.max_image_array_layers));
+		}
+
+		let images = paths.iter()
+			.map(|path| image::open(path).map(|image| image.into_luma16()).map_err(|e| SceneError::Heightmap(path.clone(), e)))
+			.collect::<Result<Vec<_>, _>>()?;
+
+		let width = images.iter().map(|image| image.width()).max().unwrap_or(1);
+		let height = images.iter().map(|image| image.height()).max().unwrap_or(1);
+		if width.max(height) > properties.max_image_dimension2_d {
+			return Err(SceneError::HeightmapsTooLarge(properties.max_image_dimension2_d));
+		}
+		let images: Vec<HeightImage> = images.into_iter()
+			.map(|image| if image.dimensions() == (width, height) { image } else { imageops::resize(&image, width, height, FilterType::Triangle) })
+			.collect();
+
+		let slopes = images.iter().map(max_slopes).collect();
+		let layers = images.len().max(1) as u32;
+		let heights: Vec<f32> = if images.is_empty() {
+			vec![0.]
+		} else {
+			images.iter().flat_map(|image| image.pixels().map(|p| p.0[0] as f32 / u16::MAX as f32)).collect()
+		};
+
+		// Sampled with texelFetch and interpolated in the shader, as linear filtering isn't guaranteed for float formats
+		let (image, future) = ImmutableImage::from_iter(
+			heights,
+			ImageDimensions::Dim2d { width, height, array_layers: layers },
+			MipmapsCount::One,
+			Format::R32_SFLOAT,
+			vk_target.queue.clone()
+		).map_err(|e| SceneError::ImageCreation(Box::new(e)))?;
+		future.then_signal_fence_and_flush()
+			.and_then(|future| future.wait(None))
+			.map_err(|e| SceneError::ImageCreation(Box::new(e)))?;
+
+		// The default view of a single layer image isn't an array, which the shader expects
+		let view = ImageView::new(image.clone(), ImageViewCreateInfo {
+			view_type: ImageViewType::Dim2dArray,
+			..ImageViewCreateInfo::from_image(&image)
+		}).unwrap();
+
+		let sampler = Sampler::new(vk_target.device.clone(), SamplerCreateInfo {
+			mag_filter: Filter::Nearest,
+			min_filter: Filter::Nearest,
+			address_mode: [SamplerAddressMode::ClampToEdge; 3],
+			..Default::default()
+		}).unwrap();
+
+		Ok(HeightmapArray {
+			paths: paths.to_vec(),
+			slopes,
+			width,
+			height,
+			view,
+			sampler
+		})
+	}
+
+	pub fn paths(&self) -> &[PathBuf] {
+		&self.paths
+	}
+
+	pub fn view(&self) -> Arc<ImageView<ImmutableImage>> {
+		self.view.clone()
+	}
+
+	pub fn sampler(&self) -> Arc<Sampler> {
+		self.sampler.clone()
+	}
+
+	/// Fills in how much the Heightmap shapes' vertical distances are scaled by to stay a lower bound on the distance
+	/// to the terrain, which depends on its steepest slope. Each shape's Shape::params[0].x is its layer
+	pub fn set_step_scales(&self, shapes: &mut [Shape]) {
+		for shape in shapes.iter_mut().filter(|shape| shape.shape_type == SHAPE_TYPE_HEIGHTMAP) {
+			let [slope_x, slope_y] = self.slopes[shape.params[0][0] as usize];
+
+			// The texels span the terrain's full width, from edge to edge
+			let texel_width = 2. * shape.size[0] / (self.width.max(2) - 1) as f32;
+			let texel_depth = 2. * shape.size[2] / (self.height.max(2) - 1) as f32;
+			let gradient = ((slope_x * shape.size[1] / texel_width).powi(2) + (slope_y * shape.size[1] / texel_depth).powi(2)).sqrt();
+
+			shape.params[0][1] = 1. / (1. + gradient * gradient).sqrt();
+		}
+	}
+}
+
+/// The most the height changes between neighbouring texels, along x and along y
+fn max_slopes(image: &HeightImage) -> [f32; 2] {
+	let mut slopes = [0u16; 2];
+	for (x, y, p) in image.enumerate_pixels() {
+		if x + 1 < image.width() {
+			slopes[0] = slopes[0].max(p.0[0].abs_diff(image.get_pixel(x + 1, y).0[0]));
+		}
+		if y + 1 < image.height() {
+			slopes[1] = slopes[1].max(p.0[0].abs_diff(image.get_pixel(x, y + 1).0[0]));
+		}
+	}
+
+	slopes.map(|s| s as f32 / u16::MAX as f32)
+}
+
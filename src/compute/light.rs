@@ -0,0 +1,251 @@
+use std::borrow::Cow;
+
+use serde::Deserialize;
+
+use super::{shaders::ray_marching_shader::ty::Light, scene::SceneError, shape::normalise, LIGHT_POINT, LIGHT_DIRECTIONAL, LIGHT_SPOT};
+
+/// The most steps a shadow ray can take towards a light
+pub const MAX_SHADOW_STEPS: u32 = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum LightKind {
+	/// Shines in every direction from the light's position
+	#[default]
+	Point,
+	/// Shines along `direction` from infinitely far away, like the sun. The light's position and range are unused
+	Directional {
+		direction: [f32; 3]
+	},
+	/// Shines from the light's position in a cone around `direction`. `inner_angle` and `outer_angle` are in radians
+	/// from the direction - the light is at full intensity within the inner angle, fading to nothing at the outer angle
+	Spot {
+		direction: [f32; 3],
+		inner_angle: f32,
+		outer_angle: f32
+	}
+}
+
+/// A light in a scene file. Lights add up, each scaled by how much of it reaches the surface
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename = "Light")]
+pub struct LightDesc {
+	#[serde(default)]
+	pub kind: LightKind,
+	#[serde(default)]
+	pub position: [f32; 3],
+	#[serde(default = "default_colour")]
+	pub colour: [f32; 3],
+	/// Scales the colour
+	#[serde(default = "default_intensity")]
+	pub intensity: f32,
+	/// How far point and spot lights reach, or None for no falloff. Within the range the light fades as
+	/// `(1 - distance / range) ^ falloff`
+	#[serde(default)]
+	pub range: Option<f32>,
+	#[serde(default = "default_falloff")]
+	pub falloff: f32,
+	/// Whether the light casts shadows. Each shadow marches a ray from every lit point towards the light, so turning
+	/// them off is faster, see [`super::Raymarch::set_shadows`]
+	#[serde(default = "default_shadows")]
+	pub shadows: bool,
+	/// How sharp the edges of the shadows are. Lower values give wider penumbrae, as from a bigger light
+	#[serde(default = "default_shadow_softness")]
+	pub shadow_softness: f32,
+	/// The most steps a shadow ray takes towards the light. Points whose rays run out of steps are treated as lit
+	#[serde(default = "default_shadow_steps")]
+	pub shadow_steps: u32
+}
+
+fn default_colour() -> [f32; 3] {
+	[1.; 3]
+}
+
+fn default_intensity() -> f32 {
+	1.
+}
+
+fn default_falloff() -> f32 {
+	2.
+}
+
+fn default_shadows() -> bool {
+	true
+}
+
+fn default_shadow_softness() -> f32 {
+	16.
+}
+
+fn default_shadow_steps() -> u32 {
+	64
+}
+
+impl LightDesc {
+	/// Creates a light of the given kind at the origin, with no falloff and casting shadows with the default
+	/// softness and step budget
+	pub fn new(kind: LightKind, colour: [f32; 3]) -> Self {
+		LightDesc {
+			kind,
+			position: [0.; 3],
+			colour,
+			intensity: default_intensity(),
+			range: None,
+			falloff: default_falloff(),
+			shadows: default_shadows(),
+			shadow_softness: default_shadow_softness(),
+			shadow_steps: default_shadow_steps()
+		}
+	}
+
+	pub fn point(position: [f32; 3], colour: [f32; 3]) -> Self {
+		Self::new(LightKind::Point, colour).position(position)
+	}
+
+	pub fn directional(direction: [f32; 3], colour: [f32; 3]) -> Self {
+		Self::new(LightKind::Directional { direction }, colour)
+	}
+
+	/// Creates a spot light, see [`LightKind::Spot`]
+	pub fn spot(position: [f32; 3], direction: [f32; 3], inner_angle: f32, outer_angle: f32, colour: [f32; 3]) -> Self {
+		Self::new(LightKind::Spot { direction, inner_angle, outer_angle }, colour).position(position)
+	}
+
+	pub fn position(mut self, position: [f32; 3]) -> Self {
+		self.position = position;
+		self
+	}
+
+	pub fn intensity(mut self, intensity: f32) -> Self {
+		self.intensity = intensity;
+		self
+	}
+
+	/// Makes the light fade to nothing at `range`, see [`LightDesc::range`]
+	pub fn range(mut self, range: f32, falloff: f32) -> Self {
+		self.range = Some(range);
+		self.falloff = falloff;
+		self
+	}
+
+	/// Makes the light cast shadows, with edges as sharp as `softness` and shadow rays taking at most `steps` steps
+	pub fn shadows(mut self, softness: f32, steps: u32) -> Self {
+		self.shadows = true;
+		self.shadow_softness = softness;
+		self.shadow_steps = steps;
+		self
+	}
+
+	/// Stops the light casting shadows, which is faster
+	pub fn no_shadows(mut self) -> Self {
+		self.shadows = false;
+		self
+	}
+
+	/// Checks the light, returning why it's invalid if it is
+	pub fn validate(&self) -> Result<(), Cow<'static, str>> {
+		let values = self.position.iter().chain(&self.colour).chain([&self.intensity, &self.falloff, &self.shadow_softness]);
+		if !values.chain(&self.range).all(|v| v.is_finite()) {
+			return Err("values must be finite".into());
+		}
+		if self.colour.iter().any(|&c| c < 0.) || self.intensity < 0. {
+			return Err("colour and intensity must not be negative".into());
+		}
+		if self.range.is_some_and(|range| range <= 0.) || self.falloff <= 0. {
+			return Err("range and falloff must be positive".into());
+		}
+		if self.shadow_softness <= 0. {
+			return Err("shadow softness must be positive".into());
+		}
+		if self.shadow_steps == 0 || self.shadow_steps > MAX_SHADOW_STEPS {
+			return Err(format!("shadow steps must be between 1 and {}", MAX_SHADOW_STEPS).into());
+		}
+
+		match self.kind {
+			LightKind::Point => (),
+			LightKind::Directional { direction } => normalise(direction).map(|_| ()).ok_or("direction must be finite and non-zero")?,
+			LightKind::Spot { direction, inner_angle, outer_angle } => {
+				normalise(direction).ok_or("direction must be finite and non-zero")?;
+				if !(outer_angle > 0. && outer_angle < std::f32::consts::PI) {
+					return Err("spot light outer angle must be between 0 and pi".into());
+				}
+				if !(inner_angle >= 0. && inner_angle <= outer_angle) {
+					return Err("spot light inner angle must be between 0 and the outer angle".into());
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Validates the light and lays it out as expected by the shader's light buffer
+	pub fn to_light(&self) -> Result<Light, Cow<'static, str>> {
+		self.validate()?;
+
+		let (light_type, direction, cos_inner, cos_outer) = match self.kind {
+			LightKind::Point => (LIGHT_POINT, [0.; 3], 0., 0.),
+			LightKind::Directional { direction } => (LIGHT_DIRECTIONAL, normalise(direction).unwrap(), 0., 0.),
+			LightKind::Spot { direction, inner_angle, outer_angle } => (LIGHT_SPOT, normalise(direction).unwrap(), inner_angle.cos(), outer_angle.cos())
+		};
+
+		Ok(Light {
+			position: self.position,
+			light_type,
+			direction,
+			intensity: self.intensity,
+			colour: self.colour,
+			range: self.range.unwrap_or(0.),
+			falloff: self.falloff,
+			cos_inner,
+			cos_outer,
+			shadow_softness: self.shadow_softness,
+			shadow_steps: if self.shadows { self.shadow_steps } else { 0 },
+			..Default::default()
+		})
+	}
+}
+
+/// Lays the lights out as expected by the shader's light buffer
+pub(super) fn to_lights(lights: &[LightDesc]) -> Result<Vec<Light>, SceneError> {
+	lights.iter().enumerate()
+		.map(|(i, light)| light.to_light().map_err(|msg| SceneError::InvalidLight(i, msg)))
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn shadow_settings_are_validated() {
+		let light = LightDesc::point([0., 10., 0.], [1.; 3]);
+		assert!(light.clone().shadows(8., MAX_SHADOW_STEPS).validate().is_ok());
+		assert!(light.clone().shadows(0., 64).validate().is_err());
+		assert!(light.clone().shadows(8., 0).validate().is_err());
+		assert!(light.shadows(8., MAX_SHADOW_STEPS + 1).validate().is_err());
+	}
+
+	#[test]
+	fn light_kinds_are_validated() {
+		assert!(LightDesc::directional([0., -1., 0.], [1.; 3]).validate().is_ok());
+		assert!(LightDesc::directional([0.; 3], [1.; 3]).validate().is_err());
+		assert!(LightDesc::spot([0.; 3], [0., -1., 0.], 0.2, 0.4, [1.; 3]).validate().is_ok());
+		assert!(LightDesc::spot([0.; 3], [0., -1., 0.], 0.5, 0.4, [1.; 3]).validate().is_err());
+		assert!(LightDesc::spot([0.; 3], [0., -1., 0.], 0.2, std::f32::consts::PI, [1.; 3]).validate().is_err());
+		assert!(LightDesc::point([0.; 3], [1.; 3]).range(0., 2.).validate().is_err());
+		assert!(LightDesc::point([0.; 3], [-1., 1., 1.]).validate().is_err());
+	}
+
+	#[test]
+	fn invalid_lights_are_reported_by_index() {
+		let lights = [LightDesc::point([0.; 3], [1.; 3]), LightDesc::point([0.; 3], [1.; 3]).intensity(f32::NAN)];
+		assert!(matches!(to_lights(&lights), Err(SceneError::InvalidLight(1, _))));
+	}
+
+	#[test]
+	fn lights_are_laid_out_with_normalised_directions() {
+		let light = LightDesc::spot([0.; 3], [0., -2., 0.], 0., 0.5, [1.; 3]).no_shadows().to_light().unwrap();
+		assert_eq!(light.light_type, LIGHT_SPOT);
+		assert_eq!(light.direction, [0., -1., 0.]);
+		assert_eq!(light.shadow_steps, 0);
+	}
+}
@@ -0,0 +1,125 @@
+use serde::Deserialize;
+
+use super::{shaders::ray_marching_shader::ty::Material, scene::SceneError};
+
+/// How a surface reflects light, shaded with the Cook-Torrance microfacet BRDF. Shapes reference materials by their
+/// index in the scene's material table, see [`super::ShapeDesc::material`]. Shapes without a material are shaded
+/// as the default material, with their own albedo
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename = "Material")]
+pub struct MaterialDesc {
+	/// The base colour - the diffuse colour of dielectrics and the specular colour of metals. Replaces the albedo of
+	/// the shapes using the material, though a palette still takes precedence
+	#[serde(default = "default_albedo")]
+	pub albedo: [f32; 3],
+	/// From 0 (a mirror-like surface with sharp highlights) to 1 (a rough surface with broad, dim highlights)
+	#[serde(default = "default_roughness")]
+	pub roughness: f32,
+	/// From 0 (a dielectric, like plastic or stone) to 1 (a metal). Values in between are mostly useful for blending
+	#[serde(default)]
+	pub metallic: f32,
+	/// Light the surface gives off itself, added regardless of the lights and occlusion. Doesn't light other surfaces
+	#[serde(default)]
+	pub emissive: [f32; 3]
+}
+
+fn default_albedo() -> [f32; 3] {
+	[1.; 3]
+}
+
+// Matches DEFAULT_ROUGHNESS in the ray marching shader, which shapes without a material use
+fn default_roughness() -> f32 {
+	0.5
+}
+
+impl Default for MaterialDesc {
+	fn default() -> Self {
+		MaterialDesc {
+			albedo: default_albedo(),
+			roughness: default_roughness(),
+			metallic: 0.,
+			emissive: [0.; 3]
+		}
+	}
+}
+
+impl MaterialDesc {
+	pub fn albedo(mut self, albedo: [f32; 3]) -> Self {
+		self.albedo = albedo;
+		self
+	}
+
+	pub fn roughness(mut self, roughness: f32) -> Self {
+		self.roughness = roughness;
+		self
+	}
+
+	pub fn metallic(mut self, metallic: f32) -> Self {
+		self.metallic = metallic;
+		self
+	}
+
+	pub fn emissive(mut self, emissive: [f32; 3]) -> Self {
+		self.emissive = emissive;
+		self
+	}
+
+	/// Checks the material, returning why it's invalid if it is
+	pub fn validate(&self) -> Result<(), &'static str> {
+		if !self.albedo.iter().chain(&self.emissive).chain([&self.roughness, &self.metallic]).all(|v| v.is_finite()) {
+			return Err("values must be finite");
+		}
+		if self.albedo.iter().any(|&a| !(0. ..=1.).contains(&a)) {
+			return Err("albedo must be between 0 and 1");
+		}
+		if !(0. ..=1.).contains(&self.roughness) || !(0. ..=1.).contains(&self.metallic) {
+			return Err("roughness and metallic must be between 0 and 1");
+		}
+		if self.emissive.iter().any(|&e| e < 0.) {
+			return Err("emissive must not be negative");
+		}
+
+		Ok(())
+	}
+
+	/// Validates the material and lays it out as expected by the shader's material buffer. The albedo is left out,
+	/// as it's given to the shapes using the material instead
+	pub fn to_material(&self) -> Result<Material, &'static str> {
+		self.validate()?;
+
+		Ok(Material {
+			emissive: self.emissive,
+			roughness: self.roughness,
+			metallic: self.metallic,
+			..Default::default()
+		})
+	}
+}
+
+/// Lays the materials out as expected by the shader's material buffer
+pub(super) fn to_materials(materials: &[MaterialDesc]) -> Result<Vec<Material>, SceneError> {
+	materials.iter().enumerate()
+		.map(|(i, material)| material.to_material().map_err(|msg| SceneError::InvalidMaterial(i, msg)))
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn materials_are_validated() {
+		let material = MaterialDesc::default().albedo([0.9, 0.6, 0.2]).roughness(0.3).metallic(1.).emissive([2., 0., 0.]);
+		assert!(material.validate().is_ok());
+		assert!(material.clone().albedo([1.5, 0., 0.]).validate().is_err());
+		assert!(material.clone().roughness(-0.1).validate().is_err());
+		assert!(material.clone().metallic(f32::NAN).validate().is_err());
+		assert!(material.emissive([-1., 0., 0.]).validate().is_err());
+	}
+
+	#[test]
+	fn invalid_materials_are_reported_by_index() {
+		let materials = [MaterialDesc::default(), MaterialDesc::default().roughness(2.)];
+		assert!(matches!(to_materials(&materials), Err(SceneError::InvalidMaterial(1, _))));
+	}
+}
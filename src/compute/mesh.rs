@@ -0,0 +1,585 @@
+use std::{fmt, fs, io::{self, Write, BufWriter}, path::Path, collections::HashMap};
+
+use super::sdf_grid::SdfGrid;
+
+/// The fewest and most points along the longest side of a grid a mesh is voxelized into
+pub const MIN_MESH_RESOLUTION: u32 = 8;
+pub const MAX_MESH_RESOLUTION: u32 = 256;
+
+/// Cells of empty space around the mesh in its grid, so the surface doesn't touch the grid's bounds
+const GRID_PADDING: u32 = 2;
+
+#[derive(Debug)]
+pub enum MeshError {
+	Io(io::Error),
+	/// The file isn't OBJ or STL, going by its extension
+	UnknownFormat,
+	/// The line of an OBJ or ASCII STL file that couldn't be parsed, with a message saying why
+	Parse(usize, &'static str),
+	/// A binary STL file is shorter than its triangle count says
+	Truncated,
+	/// The mesh has no triangles, or they're all at a single point
+	Empty,
+	NonFinite
+}
+
+impl fmt::Display for MeshError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			MeshError::Io(e) => write!(f, "Failed to read mesh file: {}", e),
+			MeshError::UnknownFormat => write!(f, "Mesh files must be OBJ (.obj) or STL (.stl)"),
+			MeshError::Parse(line, msg) => write!(f, "Failed to parse mesh file at line {}: {}", line, msg),
+			MeshError::Truncated => write!(f, "Binary STL file is shorter than its triangle count says"),
+			MeshError::Empty => write!(f, "Mesh has no triangles with any area"),
+			MeshError::NonFinite => write!(f, "Mesh has a non-finite vertex")
+		}
+	}
+}
+
+impl std::error::Error for MeshError {}
+
+impl From<io::Error> for MeshError {
+	fn from(e: io::Error) -> Self {
+		MeshError::Io(e)
+	}
+}
+
+/// The file formats meshes are loaded from and saved to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshFormat {
+	Obj,
+	/// Binary STL when saving, binary or ASCII when loading
+	Stl
+}
+
+impl MeshFormat {
+	/// The format a mesh file is in, going by its extension
+	pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, MeshError> {
+		let extension = path.as_ref().extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+		match extension.as_deref() {
+			Some("obj") => Ok(MeshFormat::Obj),
+			Some("stl") => Ok(MeshFormat::Stl),
+			_ => Err(MeshError::UnknownFormat)
+		}
+	}
+}
+
+/// A triangle mesh, as a list of triangles with their vertices in counter-clockwise order seen from outside
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Mesh {
+	pub triangles: Vec<[[f32; 3]; 3]>
+}
+
+impl Mesh {
+	/// Loads an OBJ or STL (binary or ASCII) file, going by its extension
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, MeshError> {
+		let mesh = match MeshFormat::from_path(&path)? {
+			MeshFormat::Obj => Self::parse_obj(&fs::read_to_string(path)?)?,
+			MeshFormat::Stl => Self::parse_stl(&fs::read(path)?)?
+		};
+
+		if !mesh.triangles.iter().flatten().flatten().all(|v| v.is_finite()) {
+			return Err(MeshError::NonFinite);
+		}
+		Ok(mesh)
+	}
+
+	/// Parses the vertices and faces of an OBJ file. Faces with more than 3 vertices are split into triangles
+	/// around their first vertex, everything else is ignored
+	pub fn parse_obj(src: &str) -> Result<Self, MeshError> {
+		let mut vertices = Vec::new();
+		let mut triangles = Vec::new();
+
+		for (i, line) in src.lines().enumerate() {
+			let mut words = line.split_whitespace();
+			match words.next() {
+				Some("v") => {
+					let mut vertex = [0.; 3];
+					for v in &mut vertex {
+						*v = words.next().and_then(|w| w.parse().ok()).ok_or(MeshError::Parse(i + 1, "vertex must have 3 coordinates"))?;
+					}
+					vertices.push(vertex);
+				},
+				Some("f") => {
+					// Each vertex is v, v/vt, v//vn or v/vt/vn. Negative indices count back from the latest vertex
+					let face = words.map(|w| {
+						let index: i64 = w.split('/').next().and_then(|v| v.parse().ok()).ok_or(MeshError::Parse(i + 1, "face has an invalid vertex index"))?;
+						let index = if index < 0 { vertices.len() as i64 + index } else { index - 1 };
+						vertices.get(index as usize).copied().ok_or(MeshError::Parse(i + 1, "face refers to a vertex that doesn't exist"))
+					}).collect::<Result<Vec<_>, _>>()?;
+
+					if face.len() < 3 {
+						return Err(MeshError::Parse(i + 1, "face must have at least 3 vertices"));
+					}
+					for j in 1..face.len() - 1 {
+						triangles.push([face[0], face[j], face[j + 1]]);
+					}
+				},
+				_ => ()
+			}
+		}
+
+		Ok(Mesh { triangles })
+	}
+
+	/// Parses a binary or ASCII STL file. ASCII files start with "solid", but so do some binary ones, so a
+	/// file is only treated as ASCII if it isn't the size its binary triangle count says
+	pub fn parse_stl(bytes: &[u8]) -> Result<Self, MeshError> {
+		let binary_len = bytes.get(80..84).map(|count| 84 + 50 * u32::from_le_bytes(count.try_into().unwrap()) as usize);
+		if bytes.starts_with(b"solid") && binary_len != Some(bytes.len()) {
+			let src = std::str::from_utf8(bytes).map_err(|_| MeshError::Parse(1, "ASCII STL file isn't valid UTF-8"))?;
+			return Self::parse_ascii_stl(src);
+		}
+
+		let binary_len = binary_len.ok_or(MeshError::Truncated)?;
+		if bytes.len() < binary_len {
+			return Err(MeshError::Truncated);
+		}
+
+		// Each triangle is a normal, 3 vertices and a 2 byte attribute count, all little endian
+		let triangles = bytes[84..binary_len].chunks_exact(50).map(|triangle| {
+			let float = |i: usize| f32::from_le_bytes(triangle[i * 4..i * 4 + 4].try_into().unwrap());
+			[[float(3), float(4), float(5)], [float(6), float(7), float(8)], [float(9), float(10), float(11)]]
+		}).collect();
+
+		Ok(Mesh { triangles })
+	}
+
+	fn parse_ascii_stl(src: &str) -> Result<Self, MeshError> {
+		let mut triangles = Vec::new();
+		let mut facet = Vec::new();
+
+		for (i, line) in src.lines().enumerate() {
+			let mut words = line.split_whitespace();
+			match words.next() {
+				Some("vertex") => {
+					let mut vertex = [0.; 3];
+					for v in &mut vertex {
+						*v = words.next().and_then(|w| w.parse().ok()).ok_or(MeshError::Parse(i + 1, "vertex must have 3 coordinates"))?;
+					}
+					facet.push(vertex);
+				},
+				Some("endfacet") => {
+					if facet.len() != 3 {
+						return Err(MeshError::Parse(i + 1, "facet must have 3 vertices"));
+					}
+					triangles.push([facet[0], facet[1], facet[2]]);
+					facet.clear();
+				},
+				_ => ()
+			}
+		}
+
+		Ok(Mesh { triangles })
+	}
+
+	/// Saves the mesh as an OBJ or binary STL file, going by its extension
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), MeshError> {
+		let format = MeshFormat::from_path(&path)?;
+		let mut writer = BufWriter::new(fs::File::create(path)?);
+		match format {
+			MeshFormat::Obj => self.write_obj(&mut writer)?,
+			MeshFormat::Stl => self.write_stl(&mut writer)?
+		}
+		writer.flush()?;
+
+		Ok(())
+	}
+
+	/// Writes the mesh as an OBJ file. Vertices shared by several triangles are only written once, so the
+	/// triangles are joined for programs that care
+	pub fn write_obj<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+		let mut indices = HashMap::new();
+		let mut vertices = Vec::new();
+		let faces: Vec<[usize; 3]> = self.triangles.iter().map(|triangle| triangle.map(|v| {
+			// OBJ indices start at 1
+			*indices.entry(v.map(f32::to_bits)).or_insert_with(|| {
+				vertices.push(v);
+				vertices.len()
+			})
+		})).collect();
+
+		for [x, y, z] in vertices {
+			writeln!(writer, "v {} {} {}", x, y, z)?;
+		}
+		for [a, b, c] in faces {
+			writeln!(writer, "f {} {} {}", a, b, c)?;
+		}
+
+		Ok(())
+	}
+
+	/// Writes the mesh as a binary STL file, with each triangle's normal worked out from its winding
+	pub fn write_stl<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+		let mut header = [0u8; 80];
+		let name = b"vulkano_raymarching";
+		header[..name.len()].copy_from_slice(name);
+		writer.write_all(&header)?;
+		writer.write_all(&(self.triangles.len() as u32).to_le_bytes())?;
+
+		for [a, b, c] in &self.triangles {
+			let normal = cross(sub(*b, *a), sub(*c, *a));
+			let length = dot(normal, normal).sqrt();
+			let normal = if length > 0. { normal.map(|n| n / length) } else { [0.; 3] };
+
+			for v in [normal, *a, *b, *c] {
+				for f in v {
+					writer.write_all(&f.to_le_bytes())?;
+				}
+			}
+			// Attribute byte count, which is unused
+			writer.write_all(&[0; 2])?;
+		}
+
+		Ok(())
+	}
+
+	/// The corners of the box around the mesh, or None if it has no triangles
+	pub fn bounds(&self) -> Option<([f32; 3], [f32; 3])> {
+		let mut vertices = self.triangles.iter().flatten();
+		let first = *vertices.next()?;
+
+		Some(vertices.fold((first, first), |(min, max), v| {
+			([min[0].min(v[0]), min[1].min(v[1]), min[2].min(v[2])], [max[0].max(v[0]), max[1].max(v[1]), max[2].max(v[2])])
+		}))
+	}
+
+	/// Voxelizes the mesh into a grid of signed distances to its surface, negative inside, with `resolution` points
+	/// along the longest side of the grid. The mesh should be closed, or points will be wrongly signed on rays
+	/// that pass through its holes
+	pub fn to_sdf_grid(&self, resolution: u32) -> Result<SdfGrid, MeshError> {
+		let (min, max) = self.bounds().ok_or(MeshError::Empty)?;
+		let resolution = resolution.clamp(MIN_MESH_RESOLUTION, MAX_MESH_RESOLUTION);
+
+		let longest = (0..3).map(|i| max[i] - min[i]).fold(0., f32::max);
+		if longest <= 0. {
+			return Err(MeshError::Empty);
+		}
+		let cell_size = longest / (resolution - 1 - 2 * GRID_PADDING) as f32;
+
+		let size = [0, 1, 2].map(|i| ((max[i] - min[i]) / cell_size).ceil() as u32 + 1 + 2 * GRID_PADDING);
+		// Centred on the mesh, so it's at least GRID_PADDING cells from each side
+		let origin = [0, 1, 2].map(|i| (min[i] + max[i]) / 2. - (size[i] - 1) as f32 * cell_size / 2.);
+
+		let mut voxelizer = Voxelizer::new(&self.triangles, size, origin, cell_size);
+		voxelizer.init_band();
+		voxelizer.sweep();
+		let inside = voxelizer.inside();
+
+		let distances = voxelizer.distances.iter().zip(inside).map(|(&d, inside)| if inside { -d } else { d }).collect();
+		Ok(SdfGrid::new(size, origin, cell_size, distances))
+	}
+}
+
+/// Finds the distance from each grid point to the nearest triangle, following Bridson and Batty's SDFGen: exact
+/// distances near the triangles are swept out across the grid, then signed by counting crossings along rays
+struct Voxelizer<'a> {
+	triangles: &'a [[[f32; 3]; 3]],
+	size: [u32; 3],
+	origin: [f32; 3],
+	cell_size: f32,
+	distances: Vec<f32>,
+	/// The triangle each grid point's distance was measured to, or None if it hasn't been reached yet
+	closest: Vec<Option<usize>>
+}
+
+impl<'a> Voxelizer<'a> {
+	fn new(triangles: &'a [[[f32; 3]; 3]], size: [u32; 3], origin: [f32; 3], cell_size: f32) -> Self {
+		let len = (size[0] * size[1] * size[2]) as usize;
+
+		Voxelizer {
+			triangles,
+			size,
+			origin,
+			cell_size,
+			distances: vec![f32::INFINITY; len],
+			closest: vec![None; len]
+		}
+	}
+
+	fn index(&self, x: u32, y: u32, z: u32) -> usize {
+		(x + self.size[0] * (y + self.size[1] * z)) as usize
+	}
+
+	fn point(&self, x: u32, y: u32, z: u32) -> [f32; 3] {
+		[self.origin[0] + x as f32 * self.cell_size, self.origin[1] + y as f32 * self.cell_size, self.origin[2] + z as f32 * self.cell_size]
+	}
+
+	/// Measures the exact distance to each triangle from the grid points within a cell of it
+	fn init_band(&mut self) {
+		for (t, triangle) in self.triangles.iter().enumerate() {
+			let lo = [0, 1, 2].map(|i| {
+				let v = triangle.iter().map(|v| v[i]).fold(f32::INFINITY, f32::min);
+				(((v - self.origin[i]) / self.cell_size).floor() as i64 - 1).clamp(0, self.size[i] as i64 - 1) as u32
+			});
+			let hi = [0, 1, 2].map(|i| {
+				let v = triangle.iter().map(|v| v[i]).fold(f32::NEG_INFINITY, f32::max);
+				(((v - self.origin[i]) / self.cell_size).ceil() as i64 + 1).clamp(0, self.size[i] as i64 - 1) as u32
+			});
+
+			for z in lo[2]..=hi[2] {
+				for y in lo[1]..=hi[1] {
+					for x in lo[0]..=hi[0] {
+						let i = self.index(x, y, z);
+						let d = point_triangle_distance(self.point(x, y, z), triangle);
+						if d < self.distances[i] {
+							self.distances[i] = d;
+							self.closest[i] = Some(t);
+						}
+					}
+				}
+			}
+		}
+	}
+
+	/// Spreads the closest triangles across the grid, sweeping in each of the 8 diagonal directions twice. Each
+	/// point takes its neighbours' closest triangle if it's closer than its own
+	fn sweep(&mut self) {
+		for _ in 0..2 {
+			for direction in 0..8 {
+				// Each point is visited after the neighbours it looks at, which are behind it in the sweep direction
+				let forward = [direction & 1 == 0, direction & 2 == 0, direction & 4 == 0];
+				let range = |axis: usize| -> Vec<u32> {
+					if forward[axis] { (1..self.size[axis]).collect() } else { (0..self.size[axis] - 1).rev().collect() }
+				};
+				let behind = |c: u32, axis: usize, offset: u32| if forward[axis] { c - offset } else { c + offset };
+
+				for z in range(2) {
+					for y in range(1) {
+						for x in range(0) {
+							let i = self.index(x, y, z);
+							let p = self.point(x, y, z);
+							for neighbour in 1..8 {
+								let n = self.index(behind(x, 0, neighbour & 1), behind(y, 1, (neighbour >> 1) & 1), behind(z, 2, (neighbour >> 2) & 1));
+								if let Some(t) = self.closest[n] {
+									let d = point_triangle_distance(p, &self.triangles[t]);
+									if d < self.distances[i] {
+										self.distances[i] = d;
+										self.closest[i] = Some(t);
+									}
+								}
+							}
+						}
+					}
+				}
+			}
+		}
+	}
+
+	/// Whether each grid point is inside the mesh, by whether a ray from it along -x crosses the mesh an odd
+	/// number of times
+	fn inside(&self) -> Vec<bool> {
+		// Rays exactly along a grid line would hit the edges and vertices of axis-aligned meshes, counting them twice,
+		// so they're nudged off it by an amount unlikely to line up with anything
+		let nudge = [0.000_317_9 * self.cell_size, 0.000_575_3 * self.cell_size];
+
+		let mut crossings = vec![0u32; self.distances.len()];
+		for triangle in self.triangles {
+			let [a, b, c] = triangle.map(|v| [v[1], v[2]]);
+			let lo = [1, 2].map(|i| (((triangle.iter().map(|v| v[i]).fold(f32::INFINITY, f32::min) - self.origin[i]) / self.cell_size).ceil().max(0.)) as u32);
+			let hi = [1, 2].map(|i| (((triangle.iter().map(|v| v[i]).fold(f32::NEG_INFINITY, f32::max) - self.origin[i]) / self.cell_size).floor() as i64).min(self.size[i] as i64 - 1));
+
+			for z in lo[1] as i64..=hi[1] {
+				for y in lo[0] as i64..=hi[0] {
+					let p = [self.origin[1] + y as f32 * self.cell_size + nudge[0], self.origin[2] + z as f32 * self.cell_size + nudge[1]];
+
+					// Barycentric coordinates of the ray in the triangle, projected onto the yz plane
+					let (wa, wb, wc) = (orient(p, b, c), orient(p, c, a), orient(p, a, b));
+					let area = wa + wb + wc;
+					let inside = (wa > 0. && wb > 0. && wc > 0.) || (wa < 0. && wb < 0. && wc < 0.);
+					if !inside || area == 0. {
+						continue;
+					}
+
+					let x = (wa * triangle[0][0] + wb * triangle[1][0] + wc * triangle[2][0]) / area;
+					// The crossing is counted by every grid point after it along the ray
+					let first = ((x - self.origin[0]) / self.cell_size).ceil().max(0.) as u32;
+					if first < self.size[0] {
+						crossings[self.index(first, y as u32, z as u32)] += 1;
+					}
+				}
+			}
+		}
+
+		let mut inside = vec![false; self.distances.len()];
+		for z in 0..self.size[2] {
+			for y in 0..self.size[1] {
+				let mut total = 0;
+				for x in 0..self.size[0] {
+					let i = self.index(x, y, z);
+					total += crossings[i];
+					inside[i] = total % 2 == 1;
+				}
+			}
+		}
+
+		inside
+	}
+}
+
+/// Twice the signed area of the 2D triangle (p, a, b)
+fn orient(p: [f32; 2], a: [f32; 2], b: [f32; 2]) -> f32 {
+	(a[0] - p[0]) * (b[1] - p[1]) - (a[1] - p[1]) * (b[0] - p[0])
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+	[a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+	[a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+	a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// The distance from p to the closest point on the triangle - Real-Time Collision Detection, Ericson, 5.1.5
+fn point_triangle_distance(p: [f32; 3], [a, b, c]: &[[f32; 3]; 3]) -> f32 {
+	let ab = sub(*b, *a);
+	let ac = sub(*c, *a);
+	let ap = sub(p, *a);
+	let d1 = dot(ab, ap);
+	let d2 = dot(ac, ap);
+	if d1 <= 0. && d2 <= 0. {
+		return dot(ap, ap).sqrt();
+	}
+
+	let bp = sub(p, *b);
+	let d3 = dot(ab, bp);
+	let d4 = dot(ac, bp);
+	if d3 >= 0. && d4 <= d3 {
+		return dot(bp, bp).sqrt();
+	}
+
+	let closest = |s: f32, t: f32| {
+		let q = [a[0] + ab[0] * s + ac[0] * t, a[1] + ab[1] * s + ac[1] * t, a[2] + ab[2] * s + ac[2] * t];
+		let d = sub(p, q);
+		dot(d, d).sqrt()
+	};
+
+	let vc = d1 * d4 - d3 * d2;
+	if vc <= 0. && d1 >= 0. && d3 <= 0. {
+		return closest(d1 / (d1 - d3), 0.);
+	}
+
+	let cp = sub(p, *c);
+	let d5 = dot(ab, cp);
+	let d6 = dot(ac, cp);
+	if d6 >= 0. && d5 <= d6 {
+		return dot(cp, cp).sqrt();
+	}
+
+	let vb = d5 * d2 - d1 * d6;
+	if vb <= 0. && d2 >= 0. && d6 <= 0. {
+		return closest(0., d2 / (d2 - d6));
+	}
+
+	let va = d3 * d6 - d5 * d4;
+	if va <= 0. && d4 - d3 >= 0. && d5 - d6 >= 0. {
+		// On edge bc
+		let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+		return closest(1. - w, w);
+	}
+
+	let denom = 1. / (va + vb + vc);
+	closest(vb * denom, vc * denom)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A cube from -1 to 1, with its sides as quads
+	const CUBE_OBJ: &str = "\
+v -1 -1 -1
+v 1 -1 -1
+v 1 1 -1
+v -1 1 -1
+v -1 -1 1
+v 1 -1 1
+v 1 1 1
+v -1 1 1
+f 1 4 3 2
+f 5 6 7 8
+f 1 2 6 5
+f 2 3 7 6
+f 3 4 8 7
+f 4 1 5 8
+";
+
+	#[test]
+	fn obj_polygons_are_split_around_their_first_vertex() {
+		let mesh = Mesh::parse_obj("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvn 0 0 1\nf 1/1/1 2/2/1 3//1 4\n").unwrap();
+		assert_eq!(mesh.triangles, vec![
+			[[0., 0., 0.], [1., 0., 0.], [1., 1., 0.]],
+			[[0., 0., 0.], [1., 1., 0.], [0., 1., 0.]]
+		]);
+	}
+
+	#[test]
+	fn obj_negative_indices_count_back_from_the_latest_vertex() {
+		let mesh = Mesh::parse_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\nv 0 0 1\nf -4 -1 -2\n").unwrap();
+		assert_eq!(mesh.triangles, vec![
+			[[0., 0., 0.], [1., 0., 0.], [0., 1., 0.]],
+			[[0., 0., 0.], [0., 0., 1.], [0., 1., 0.]]
+		]);
+	}
+
+	#[test]
+	fn obj_faces_must_refer_to_existing_vertices() {
+		assert!(matches!(Mesh::parse_obj("v 0 0 0\nv 1 0 0\nf 1 2 3\n"), Err(MeshError::Parse(3, _))));
+		assert!(matches!(Mesh::parse_obj("v 0 0 0\nf -2 1 1\n"), Err(MeshError::Parse(2, _))));
+		assert!(matches!(Mesh::parse_obj("v 0 0 0\nv 1 0 0\nf 1 2\n"), Err(MeshError::Parse(3, _))));
+	}
+
+	#[test]
+	fn binary_stl_starting_with_solid_is_parsed_as_binary() {
+		let mesh = Mesh::parse_obj(CUBE_OBJ).unwrap();
+		let mut bytes = Vec::new();
+		mesh.write_stl(&mut bytes).unwrap();
+		bytes[..5].copy_from_slice(b"solid");
+
+		assert_eq!(Mesh::parse_stl(&bytes).unwrap(), mesh);
+	}
+
+	#[test]
+	fn ascii_stl_is_parsed() {
+		let src = "\
+solid triangle
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid triangle
+";
+		let mesh = Mesh::parse_stl(src.as_bytes()).unwrap();
+		assert_eq!(mesh.triangles, vec![[[0., 0., 0.], [1., 0., 0.], [0., 1., 0.]]]);
+	}
+
+	#[test]
+	fn voxelized_cube_is_negative_inside_and_positive_outside() {
+		let grid = Mesh::parse_obj(CUBE_OBJ).unwrap().to_sdf_grid(24).unwrap();
+		let [size_x, size_y, size_z] = grid.size();
+
+		for z in 0..size_z {
+			for y in 0..size_y {
+				for x in 0..size_x {
+					let p = [x, y, z].map(|c| c as f32 * grid.cell_size());
+					let q = [0, 1, 2].map(|i| (grid.origin()[i] + p[i]).abs() - 1.);
+					let outside = q.iter().map(|c| c.max(0.).powi(2)).sum::<f32>().sqrt();
+					let exact = outside + q[0].max(q[1]).max(q[2]).min(0.);
+
+					let d = grid.distances()[(x + size_x * (y + size_y * z)) as usize];
+					assert!((d - exact).abs() < grid.cell_size(), "distance at {:?} is {}, not {}", [x, y, z], d, exact);
+					if exact.abs() > grid.cell_size() {
+						assert_eq!(d < 0., exact < 0., "point {:?} has the wrong sign", [x, y, z]);
+					}
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,127 @@
+use std::borrow::Cow;
+
+use serde::Deserialize;
+
+use super::{shaders::ray_marching_shader::ty::Modifier, noise::{NoiseKind, MAX_NOISE_OCTAVES, default_octaves}, MODIFIER_TWIST, MODIFIER_BEND, MODIFIER_SINE_DISPLACEMENT, MODIFIER_NOISE_DISPLACEMENT, MODIFIER_ROUND, MODIFIER_ONION, MODIFIER_COLOUR_NOISE};
+
+/// The most modifiers a shape can have
+pub const MAX_MODIFIERS: usize = 8;
+
+/// Deforms or recolours a shape. A shape's twists and bends are applied to each point in order before the shape is
+/// evaluated, then the rest are applied to the distance or colour in order. Noise is sampled in the shape's
+/// local space, after the twists and bends, so it moves with the shape
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum ModifierDesc {
+	/// Twists about the y axis, by `rate` radians per unit along it
+	Twist {
+		rate: f32
+	},
+	/// Bends the x axis towards y, by `rate` radians per unit along x
+	Bend {
+		rate: f32
+	},
+	/// Ripples the surface by a sine wave along each axis
+	SineDisplacement {
+		amplitude: f32,
+		frequency: f32
+	},
+	/// Roughens the surface by noise. More than one octave sums the noise as FBM, see [`super::NoiseSettings`]
+	NoiseDisplacement {
+		amplitude: f32,
+		frequency: f32,
+		#[serde(default)]
+		noise: NoiseKind,
+		#[serde(default = "default_octaves")]
+		octaves: u32
+	},
+	/// Grows the shape by `radius`, rounding off its edges
+	Round {
+		radius: f32
+	},
+	/// Hollows the shape into a shell `thickness` either side of the surface. Can be repeated for shells within shells
+	Onion {
+		thickness: f32
+	},
+	/// Varies the shape's colour, mixing up to `strength` of `colour` in where the noise is high
+	ColourNoise {
+		colour: [f32; 3],
+		strength: f32,
+		frequency: f32,
+		#[serde(default)]
+		noise: NoiseKind,
+		#[serde(default = "default_octaves")]
+		octaves: u32
+	}
+}
+
+impl ModifierDesc {
+	/// Checks the modifier, returning why it's invalid if it is
+	pub fn validate(&self) -> Result<(), Cow<'static, str>> {
+		let values: &[f32] = match self {
+			ModifierDesc::Twist { rate } | ModifierDesc::Bend { rate } => &[*rate],
+			ModifierDesc::SineDisplacement { amplitude, frequency } | ModifierDesc::NoiseDisplacement { amplitude, frequency, .. } => &[*amplitude, *frequency],
+			ModifierDesc::Round { radius } => &[*radius],
+			ModifierDesc::Onion { thickness } => &[*thickness],
+			ModifierDesc::ColourNoise { colour, strength, frequency, .. } => &[colour[0], colour[1], colour[2], *strength, *frequency]
+		};
+		if !values.iter().all(|v| v.is_finite()) {
+			return Err("values must be finite".into());
+		}
+
+		match *self {
+			ModifierDesc::SineDisplacement { frequency, .. } | ModifierDesc::NoiseDisplacement { frequency, .. } | ModifierDesc::ColourNoise { frequency, .. } if frequency < 0. => {
+				Err("frequency must not be negative".into())
+			},
+			ModifierDesc::NoiseDisplacement { octaves, .. } | ModifierDesc::ColourNoise { octaves, .. } if octaves == 0 || octaves > MAX_NOISE_OCTAVES => {
+				Err(format!("octaves must be between 1 and {}", MAX_NOISE_OCTAVES).into())
+			},
+			ModifierDesc::ColourNoise { strength, .. } if !(0. ..=1.).contains(&strength) => Err("colour noise strength must be between 0 and 1".into()),
+			ModifierDesc::Round { radius } if radius < 0. => Err("round radius must not be negative".into()),
+			ModifierDesc::Onion { thickness } if thickness <= 0. => Err("onion thickness must be positive".into()),
+			_ => Ok(())
+		}
+	}
+
+	/// Whether the modifier stretches distances by more the further the shape reaches, so can only be applied to
+	/// shapes with a bounding radius, see [`super::ShapeDesc::bounding_radius`]
+	pub fn needs_bounded_shape(&self) -> bool {
+		matches!(self, ModifierDesc::Twist { .. } | ModifierDesc::Bend { .. })
+	}
+
+	/// Validates the modifier and lays it out as expected by the shader's modifier buffer. `radius` bounds
+	/// how far the shape reaches from its centre, which twists and bends need to keep the distance a lower bound
+	pub fn to_modifier(self, radius: Option<f32>) -> Result<Modifier, Cow<'static, str>> {
+		self.validate()?;
+
+		// The most the modifier can stretch distances by, which the distance is divided by so the ray doesn't overshoot.
+		// FBM can stretch them further, which the shader accounts for as it depends on the scene's noise settings
+		let stretch = match self {
+			ModifierDesc::Twist { rate } | ModifierDesc::Bend { rate } => {
+				let radius = radius.ok_or("twists and bends can't be applied to unbounded shapes or shapes repeated forever")?;
+				(1. + (rate * radius).powi(2)).sqrt()
+			},
+			ModifierDesc::SineDisplacement { amplitude, frequency } => 1. + (amplitude * frequency).abs() * 3f32.sqrt(),
+			ModifierDesc::NoiseDisplacement { amplitude, frequency, noise, .. } => 1. + (amplitude * frequency).abs() * noise.max_slope(),
+			ModifierDesc::Round { .. } | ModifierDesc::Onion { .. } | ModifierDesc::ColourNoise { .. } => 1.
+		};
+
+		let (op, params, colour, noise, octaves) = match self {
+			ModifierDesc::Twist { rate } => (MODIFIER_TWIST, [rate, 0., 0.], [0.; 3], NoiseKind::default(), 1),
+			ModifierDesc::Bend { rate } => (MODIFIER_BEND, [rate, 0., 0.], [0.; 3], NoiseKind::default(), 1),
+			ModifierDesc::SineDisplacement { amplitude, frequency } => (MODIFIER_SINE_DISPLACEMENT, [amplitude, frequency, 0.], [0.; 3], NoiseKind::default(), 1),
+			ModifierDesc::NoiseDisplacement { amplitude, frequency, noise, octaves } => (MODIFIER_NOISE_DISPLACEMENT, [amplitude, frequency, 0.], [0.; 3], noise, octaves),
+			ModifierDesc::Round { radius } => (MODIFIER_ROUND, [radius, 0., 0.], [0.; 3], NoiseKind::default(), 1),
+			ModifierDesc::Onion { thickness } => (MODIFIER_ONION, [thickness, 0., 0.], [0.; 3], NoiseKind::default(), 1),
+			ModifierDesc::ColourNoise { colour, strength, frequency, noise, octaves } => (MODIFIER_COLOUR_NOISE, [strength, frequency, 0.], colour, noise, octaves)
+		};
+
+		Ok(Modifier {
+			params: [params[0], params[1], params[2], 1. / stretch],
+			colour,
+			noise_type: noise.id(),
+			op,
+			octaves,
+			_dummy0: [0; 8],
+		})
+	}
+}
@@ -0,0 +1,85 @@
+use serde::Deserialize;
+
+use super::{NOISE_VALUE, NOISE_GRADIENT, NOISE_SIMPLEX};
+
+/// The most octaves of noise that can be summed into FBM
+pub const MAX_NOISE_OCTAVES: u32 = 8;
+
+/// The noise function a modifier samples, see [`super::ModifierDesc`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum NoiseKind {
+	/// Interpolates random values at the corners of a grid. Cheapest, but blocky
+	#[default]
+	Value,
+	/// Perlin noise - Interpolates random gradients at the corners of a grid
+	Gradient,
+	/// Sums random gradients at the corners of a grid of tetrahedra. Has fewer grid artifacts than gradient noise
+	Simplex
+}
+
+impl NoiseKind {
+	pub(super) fn id(self) -> u32 {
+		match self {
+			NoiseKind::Value => NOISE_VALUE,
+			NoiseKind::Gradient => NOISE_GRADIENT,
+			NoiseKind::Simplex => NOISE_SIMPLEX
+		}
+	}
+
+	/// The most a single octave of the noise changes by per unit in any direction, at a frequency of 1
+	pub(super) fn max_slope(self) -> f32 {
+		match self {
+			NoiseKind::Value | NoiseKind::Gradient => 3.,
+			NoiseKind::Simplex => 7.
+		}
+	}
+}
+
+/// Settings shared by all the noise in a scene. Noise with more than one octave is summed as FBM (fractal
+/// Brownian motion), each octave `lacunarity` times the frequency and `gain` times the amplitude of the last
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct NoiseSettings {
+	/// Changing the seed gives different noise everywhere, with the same character
+	#[serde(default)]
+	pub seed: u32,
+	#[serde(default = "default_lacunarity")]
+	pub lacunarity: f32,
+	#[serde(default = "default_gain")]
+	pub gain: f32
+}
+
+fn default_lacunarity() -> f32 {
+	2.
+}
+
+fn default_gain() -> f32 {
+	0.5
+}
+
+impl Default for NoiseSettings {
+	fn default() -> Self {
+		NoiseSettings {
+			seed: 0,
+			lacunarity: default_lacunarity(),
+			gain: default_gain()
+		}
+	}
+}
+
+impl NoiseSettings {
+	/// Checks the settings, returning why they're invalid if they are
+	pub fn validate(&self) -> Result<(), &'static str> {
+		if !self.lacunarity.is_finite() || self.lacunarity < 1. {
+			return Err("lacunarity must be at least 1");
+		}
+		if !self.gain.is_finite() || self.gain <= 0. || self.gain > 1. {
+			return Err("gain must be greater than 0 and at most 1");
+		}
+
+		Ok(())
+	}
+}
+
+pub(super) fn default_octaves() -> u32 {
+	1
+}
@@ -0,0 +1,77 @@
+use std::borrow::Cow;
+
+use serde::Deserialize;
+
+/// The most samples taken along the normal for each point's ambient occlusion
+pub const MAX_AO_SAMPLES: u32 = 16;
+
+/// Ambient occlusion estimated from the distance field, darkening creases and cavities. The scene is sampled at
+/// `samples` points along each surface normal, `step` apart - where it's nearer than a point's distance from the
+/// surface, something is occluding it. Samples further out count for less
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct AmbientOcclusion {
+	/// No samples turns ambient occlusion off
+	#[serde(default = "default_samples")]
+	pub samples: u32,
+	/// The distance between samples. The furthest occluders that darken a point are `samples * step` away
+	#[serde(default = "default_step")]
+	pub step: f32,
+	/// How much fully occluded points are darkened, from 0 (not at all) to 1 (black)
+	#[serde(default = "default_strength")]
+	pub strength: f32
+}
+
+fn default_samples() -> u32 {
+	5
+}
+
+fn default_step() -> f32 {
+	0.05
+}
+
+fn default_strength() -> f32 {
+	1.
+}
+
+impl Default for AmbientOcclusion {
+	fn default() -> Self {
+		AmbientOcclusion {
+			samples: default_samples(),
+			step: default_step(),
+			strength: default_strength()
+		}
+	}
+}
+
+impl AmbientOcclusion {
+	/// Checks the settings, returning why they're invalid if they are
+	pub fn validate(&self) -> Result<(), Cow<'static, str>> {
+		if self.samples > MAX_AO_SAMPLES {
+			return Err(format!("samples must be at most {}", MAX_AO_SAMPLES).into());
+		}
+		if !self.step.is_finite() || self.step <= 0. {
+			return Err("step must be positive".into());
+		}
+		if !self.strength.is_finite() || !(0. ..=1.).contains(&self.strength) {
+			return Err("strength must be between 0 and 1".into());
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn settings_are_validated() {
+		let ao = AmbientOcclusion::default();
+		assert!(ao.validate().is_ok());
+		assert!(AmbientOcclusion { samples: 0, ..ao }.validate().is_ok());
+		assert!(AmbientOcclusion { samples: MAX_AO_SAMPLES + 1, ..ao }.validate().is_err());
+		assert!(AmbientOcclusion { step: 0., ..ao }.validate().is_err());
+		assert!(AmbientOcclusion { step: f32::INFINITY, ..ao }.validate().is_err());
+		assert!(AmbientOcclusion { strength: 1.5, ..ao }.validate().is_err());
+	}
+}
@@ -0,0 +1,142 @@
+use std::fmt;
+
+use serde::Deserialize;
+
+use super::{shaders::ray_marching_shader::ty::Palette, PALETTE_SOURCE_ITERATIONS, PALETTE_SOURCE_SMOOTH_ITERATIONS, PALETTE_SOURCE_POINT_TRAP, PALETTE_SOURCE_PLANE_TRAP};
+
+/// The most colour stops a palette can have, matching the size of the shader's Palette::stops
+pub const MAX_PALETTE_STOPS: usize = 8;
+
+/// The per-hit data a fractal shape outputs that a palette can be looked up by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum PaletteSource {
+	/// The number of iterations before the point escaped, as a fraction of the shape's iteration limit
+	Iterations,
+	/// The iteration count, smoothed so there are no bands between iterations
+	#[default]
+	SmoothIterations,
+	/// The closest the point's orbit came to the shape's centre
+	PointTrap,
+	/// The closest the point's orbit came to any of the planes through the shape's centre along its axes
+	PlaneTrap
+}
+
+impl PaletteSource {
+	fn id(self) -> u32 {
+		match self {
+			PaletteSource::Iterations => PALETTE_SOURCE_ITERATIONS,
+			PaletteSource::SmoothIterations => PALETTE_SOURCE_SMOOTH_ITERATIONS,
+			PaletteSource::PointTrap => PALETTE_SOURCE_POINT_TRAP,
+			PaletteSource::PlaneTrap => PALETTE_SOURCE_PLANE_TRAP
+		}
+	}
+}
+
+/// A colour at a position along a palette's gradient, from 0 to 1
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct PaletteStop {
+	pub position: f32,
+	pub colour: [f32; 3]
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PaletteError {
+	NonFinite,
+	/// A palette needs between 1 and MAX_PALETTE_STOPS stops
+	StopCount(usize),
+	/// Stops must be in order of position, between 0 and 1
+	InvalidStops
+}
+
+impl fmt::Display for PaletteError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			PaletteError::NonFinite => write!(f, "Palette has a non-finite stop, scale or offset"),
+			PaletteError::StopCount(count) => write!(f, "Palette must have between 1 and {} stops, but has {}", MAX_PALETTE_STOPS, count),
+			PaletteError::InvalidStops => write!(f, "Palette stops must be in order of position, between 0 and 1")
+		}
+	}
+}
+
+impl std::error::Error for PaletteError {}
+
+/// Colours a fractal shape by a gradient instead of its albedo. The value from `source` is multiplied by
+/// `scale` and has `offset` added, then either wraps around the gradient (if `repeat`) or is clamped to it
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PaletteDesc {
+	#[serde(default)]
+	pub source: PaletteSource,
+	pub stops: Vec<PaletteStop>,
+	#[serde(default = "default_palette_scale")]
+	pub scale: f32,
+	#[serde(default)]
+	pub offset: f32,
+	#[serde(default)]
+	pub repeat: bool
+}
+
+fn default_palette_scale() -> f32 {
+	1.
+}
+
+impl PaletteDesc {
+	/// Creates a palette looked up by smooth iterations, with the stops spaced evenly along it
+	pub fn new(colours: &[[f32; 3]]) -> Self {
+		let spacing = 1. / (colours.len().max(2) - 1) as f32;
+
+		PaletteDesc {
+			source: PaletteSource::default(),
+			stops: colours.iter().enumerate().map(|(i, &colour)| PaletteStop { position: i as f32 * spacing, colour }).collect(),
+			scale: default_palette_scale(),
+			offset: 0.,
+			repeat: false
+		}
+	}
+
+	pub fn source(mut self, source: PaletteSource) -> Self {
+		self.source = source;
+		self
+	}
+
+	/// Sets how the source value is mapped onto the gradient, see [`PaletteDesc`]
+	pub fn mapping(mut self, scale: f32, offset: f32, repeat: bool) -> Self {
+		self.scale = scale;
+		self.offset = offset;
+		self.repeat = repeat;
+		self
+	}
+
+	pub fn validate(&self) -> Result<(), PaletteError> {
+		if !self.stops.iter().flat_map(|s| s.colour.iter().chain([&s.position])).chain([&self.scale, &self.offset]).all(|v| v.is_finite()) {
+			return Err(PaletteError::NonFinite);
+		}
+		if self.stops.is_empty() || self.stops.len() > MAX_PALETTE_STOPS {
+			return Err(PaletteError::StopCount(self.stops.len()));
+		}
+		if self.stops.iter().any(|s| s.position < 0. || s.position > 1.) || self.stops.windows(2).any(|w| w[0].position > w[1].position) {
+			return Err(PaletteError::InvalidStops);
+		}
+
+		Ok(())
+	}
+
+	/// Validates the palette and lays it out as expected by the shader's palette buffer
+	pub fn to_palette(&self) -> Result<Palette, PaletteError> {
+		self.validate()?;
+
+		let mut stops = [[0.; 4]; MAX_PALETTE_STOPS];
+		for (stop, desc) in stops.iter_mut().zip(&self.stops) {
+			*stop = [desc.colour[0], desc.colour[1], desc.colour[2], desc.position];
+		}
+
+		Ok(Palette {
+			stops,
+			source: self.source.id(),
+			num_stops: self.stops.len() as u32,
+			scale: self.scale,
+			offset: self.offset,
+			repeat: self.repeat as u32,
+			_dummy0: [0; 12],
+		})
+	}
+}
@@ -0,0 +1,429 @@
+use std::{borrow::Cow, fmt, fs, io, path::{Path, PathBuf}, time::SystemTime};
+
+use serde::Deserialize;
+
+use ron::extensions::Extensions;
+
+use super::{shaders::ray_marching_shader::ty::SceneInfo, noise::NoiseSettings, occlusion::AmbientOcclusion, light::LightDesc, material::MaterialDesc, mesh::MeshError, sdf_grid::GridError, shape::{ShapeDesc, ShapeError}, csg::{CsgNode, FlatScene, CSG_STACK_SIZE, MAX_TRANSFORM_DEPTH}, OUTPUT_MODE_SHADED};
+
+#[derive(Debug)]
+pub enum SceneError {
+	Io(io::Error),
+	Parse(ron::error::SpannedError),
+	/// The shape at the given index (counting depth first, with groups counted before their children) is invalid
+	Shape(usize, ShapeError),
+	/// The shape at the given index has a parent index that's out of range, or outside the group the shape is in
+	InvalidParent(usize, usize),
+	/// The shape at the given index is its own ancestor through its chain of parents
+	ParentCycle(usize),
+	EmptyGroup,
+	NonFiniteGroup,
+	/// A group's rotation quaternion is zero, or its scale isn't positive
+	InvalidGroupTransform,
+	/// A smoothly blended group has a blend strength that isn't positive
+	NonPositiveBlendStrength(f32),
+	/// A group's domain operator is invalid, with a message saying why
+	InvalidGroupDomain(&'static str),
+	/// The scene's noise settings are invalid, with a message saying why
+	InvalidNoise(&'static str),
+	/// The light at the given index is invalid, with a message saying why
+	InvalidLight(usize, Cow<'static, str>),
+	/// There's no light at the given index to remove or replace
+	NoSuchLight(usize),
+	/// The material at the given index is invalid, with a message saying why
+	InvalidMaterial(usize, &'static str),
+	/// The shape at the given index uses a material that isn't in the scene's material table
+	UnknownMaterial(usize, usize),
+	/// The scene's ambient occlusion settings are invalid, with a message saying why
+	InvalidAmbientOcclusion(Cow<'static, str>),
+	/// The heightmap image at the given path failed to load
+	Heightmap(PathBuf, image::ImageError),
+	/// Heightmaps are larger than the largest 2D image the device supports along a side, which is given
+	HeightmapsTooLarge(u32),
+	/// There are more heightmaps than the most layers an image the device supports can have, which is given
+	TooManyHeightmaps(u32),
+	/// A heightmap or volume image couldn't be created and uploaded, e.g. because the device is out of memory
+	ImageCreation(Box<dyn std::error::Error + Send + Sync>),
+	/// The mesh file at the given path failed to load or voxelize
+	Mesh(PathBuf, MeshError),
+	/// The grid file at the given path failed to load
+	Grid(PathBuf, GridError),
+	/// The scene's volumes don't fit along an axis of the largest 3D image the device supports, which is given
+	VolumesTooLarge(u32),
+	/// The CSG tree needs a bigger stack than the shader has to evaluate
+	CsgTooDeep,
+	/// A shape has more transforms applying to it than the shader supports
+	TransformTooDeep
+}
+
+impl fmt::Display for SceneError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			SceneError::Io(e) => write!(f, "Failed to read scene file: {}", e),
+			SceneError::Parse(e) => write!(f, "Failed to parse scene file: {}", e),
+			SceneError::Shape(i, e) => write!(f, "Invalid shape at index {}: {}", i, e),
+			SceneError::InvalidParent(i, p) => write!(f, "Shape at index {} has parent {}, which isn't in the scene or in the group the shape is in", i, p),
+			SceneError::ParentCycle(i) => write!(f, "Shape at index {} is its own parent through its chain of parents", i),
+			SceneError::EmptyGroup => write!(f, "Groups must have at least one child"),
+			SceneError::NonFiniteGroup => write!(f, "Group has a non-finite transform or blend strength"),
+			SceneError::InvalidGroupTransform => write!(f, "Groups must have a non-zero rotation and a positive scale"),
+			SceneError::NonPositiveBlendStrength(strength) => write!(f, "Group is smoothly blended so must have a positive blend strength, but has blend strength {}", strength),
+			SceneError::InvalidGroupDomain(msg) => write!(f, "Group has an invalid domain operator: {}", msg),
+			SceneError::InvalidNoise(msg) => write!(f, "Invalid noise settings: {}", msg),
+			SceneError::InvalidLight(index, msg) => write!(f, "Light {} is invalid: {}", index, msg),
+			SceneError::NoSuchLight(index) => write!(f, "There is no light {}", index),
+			SceneError::InvalidMaterial(index, msg) => write!(f, "Material {} is invalid: {}", index, msg),
+			SceneError::UnknownMaterial(shape, material) => write!(f, "Shape {} uses material {}, which doesn't exist", shape, material),
+			SceneError::InvalidAmbientOcclusion(msg) => write!(f, "Invalid ambient occlusion settings: {}", msg),
+			SceneError::Heightmap(path, e) => write!(f, "Failed to load heightmap {}: {}", path.display(), e),
+			SceneError::HeightmapsTooLarge(max) => write!(f, "Heightmaps are too large, they can be at most {} pixels along each side", max),
+			SceneError::TooManyHeightmaps(max) => write!(f, "Scenes can have at most {} different heightmaps", max),
+			SceneError::ImageCreation(e) => write!(f, "Failed to create image: {}", e),
+			SceneError::Mesh(path, e) => write!(f, "Failed to load mesh {}: {}", path.display(), e),
+			SceneError::Grid(path, e) => write!(f, "Failed to load grid {}: {}", path.display(), e),
+			SceneError::VolumesTooLarge(max) => write!(f, "Volumes are too large to fit in one image, which can be at most {} points along each axis. Try lowering their resolution", max),
+			SceneError::CsgTooDeep => write!(f, "Shapes are nested too deeply, at most {} can be waiting to be combined at once", CSG_STACK_SIZE),
+			SceneError::TransformTooDeep => write!(f, "Shapes can have at most {} transforms applied, including those of their parents", MAX_TRANSFORM_DEPTH)
+		}
+	}
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<io::Error> for SceneError {
+	fn from(e: io::Error) -> Self {
+		SceneError::Io(e)
+	}
+}
+
+impl From<ron::error::SpannedError> for SceneError {
+	fn from(e: ron::error::SpannedError) -> Self {
+		SceneError::Parse(e)
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CameraDesc {
+	pub position: [f32; 3],
+	pub look_at: [f32; 3],
+	pub canvas_dist: f32
+}
+
+/// A scene as described in a scene file (RON format, see scenes/default.ron)
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename = "Scene")]
+pub struct SceneDesc {
+	pub camera: CameraDesc,
+	#[serde(default)]
+	pub lights: Vec<LightDesc>,
+	#[serde(default)]
+	pub noise: NoiseSettings,
+	#[serde(default)]
+	pub ambient_occlusion: AmbientOcclusion,
+	/// The materials shapes reference by index, see [`MaterialDesc`]
+	#[serde(default)]
+	pub materials: Vec<MaterialDesc>,
+	pub shapes: Vec<CsgNode>
+}
+
+impl SceneDesc {
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SceneError> {
+		let src = fs::read_to_string(&path)?;
+		let mut scene = Self::parse(&src)?;
+
+		// Files the scene references are relative to the scene file
+		if let Some(dir) = path.as_ref().parent() {
+			resolve_paths(&mut scene.shapes, dir);
+		}
+		Ok(scene)
+	}
+
+	pub fn parse(src: &str) -> Result<Self, SceneError> {
+		// Allows writing Shape(kind: Sphere) rather than Shape((kind: Sphere))
+		let scene: Self = ron::Options::default()
+			.with_default_extension(Extensions::UNWRAP_VARIANT_NEWTYPES)
+			.from_str(src)?;
+		scene.validate()?;
+		Ok(scene)
+	}
+
+	pub fn validate(&self) -> Result<(), SceneError> {
+		self.flatten().map(|_| ())
+	}
+
+	/// Lays the scene's shapes out as expected by the shader's shape and CSG instruction buffers
+	pub fn flatten(&self) -> Result<FlatScene, SceneError> {
+		self.noise.validate().map_err(SceneError::InvalidNoise)?;
+		for (i, light) in self.lights.iter().enumerate() {
+			light.validate().map_err(|msg| SceneError::InvalidLight(i, msg))?;
+		}
+		self.ambient_occlusion.validate().map_err(SceneError::InvalidAmbientOcclusion)?;
+		FlatScene::flatten(&self.shapes, &self.materials)
+	}
+
+	/// Lays the scene out as the SceneInfo uniform expected by the shader
+	pub fn to_scene_info(&self, flat: &FlatScene) -> SceneInfo {
+		SceneInfo {
+			camera_pos: self.camera.position,
+			look_at: self.camera.look_at,
+			canvas_dist: self.camera.canvas_dist,
+			num_shapes: flat.shapes.len() as u32,
+			noise_seed: self.noise.seed,
+			noise_lacunarity: self.noise.lacunarity,
+			noise_gain: self.noise.gain,
+			num_instructions: flat.instructions.len() as u32,
+			num_lights: self.lights.len() as u32,
+			shadows: 1,
+			ao_samples: self.ambient_occlusion.samples,
+			ao_step: self.ambient_occlusion.step,
+			ao_strength: self.ambient_occlusion.strength,
+			output_mode: OUTPUT_MODE_SHADED,
+			_dummy0: [0; 4],
+		}
+	}
+}
+
+/// Adds the heightmaps, meshes and grids the nodes reference to `files`
+fn referenced_files(nodes: &[CsgNode], files: &mut Vec<PathBuf>) {
+	for node in nodes {
+		match node {
+			CsgNode::Shape(shape) => files.extend(shape.heightmap.iter().chain(&shape.mesh).chain(&shape.grid).cloned()),
+			CsgNode::Group(group) => referenced_files(&group.children, files)
+		}
+	}
+}
+
+/// Makes the relative paths of the files the nodes reference relative to `dir` rather than the working directory
+fn resolve_paths(nodes: &mut [CsgNode], dir: &Path) {
+	for node in nodes {
+		match node {
+			CsgNode::Shape(shape) => {
+				for path in shape.heightmap.iter_mut().chain(&mut shape.mesh).chain(&mut shape.grid) {
+					*path = dir.join(&*path);
+				}
+			},
+			CsgNode::Group(group) => resolve_paths(&mut group.children, dir)
+		}
+	}
+}
+
+/// Builds a scene in code rather than loading it from a scene file
+/// ```ignore
+/// let scene = SceneBuilder::new()
+///     .shape(ShapeDesc::new(ShapeKind::Sphere).position([1., 0., 0.]).uniform_size(0.5))
+///     .build()?;
+/// ```
+pub struct SceneBuilder {
+	scene: SceneDesc
+}
+
+impl SceneBuilder {
+	/// Starts an empty scene with the camera on the z axis looking at the origin, and a white light above and behind it
+	pub fn new() -> Self {
+		SceneBuilder {
+			scene: SceneDesc {
+				camera: CameraDesc {
+					position: [0., 0., 10.],
+					look_at: [0., 0., 0.],
+					canvas_dist: 10.
+				},
+				lights: vec![LightDesc::point([0., 100., 200.], [1., 1., 1.])],
+				noise: NoiseSettings::default(),
+				ambient_occlusion: AmbientOcclusion::default(),
+				materials: Vec::new(),
+				shapes: Vec::new()
+			}
+		}
+	}
+
+	pub fn camera(mut self, position: [f32; 3], look_at: [f32; 3], canvas_dist: f32) -> Self {
+		self.scene.camera = CameraDesc { position, look_at, canvas_dist };
+		self
+	}
+
+	/// Adds a light alongside the others, see [`LightDesc`]
+	pub fn light(mut self, light: LightDesc) -> Self {
+		self.scene.lights.push(light);
+		self
+	}
+
+	/// Replaces all the lights, including the default one
+	pub fn lights(mut self, lights: Vec<LightDesc>) -> Self {
+		self.scene.lights = lights;
+		self
+	}
+
+	/// Sets the seed and FBM settings shared by all the noise in the scene, see [`NoiseSettings`]
+	pub fn noise(mut self, noise: NoiseSettings) -> Self {
+		self.scene.noise = noise;
+		self
+	}
+
+	/// Sets how creases and cavities are darkened, see [`AmbientOcclusion`]
+	pub fn ambient_occlusion(mut self, ambient_occlusion: AmbientOcclusion) -> Self {
+		self.scene.ambient_occlusion = ambient_occlusion;
+		self
+	}
+
+	/// Adds a material to the scene's material table, which shapes reference by its index, see [`MaterialDesc`]
+	pub fn material(mut self, material: MaterialDesc) -> Self {
+		self.scene.materials.push(material);
+		self
+	}
+
+	pub fn shape(self, shape: ShapeDesc) -> Self {
+		self.node(shape)
+	}
+
+	/// Adds a shape or group, see [`CsgNode`]
+	pub fn node<N: Into<CsgNode>>(mut self, node: N) -> Self {
+		self.scene.shapes.push(node.into());
+		self
+	}
+
+	pub fn build(self) -> Result<SceneDesc, SceneError> {
+		self.scene.validate()?;
+		Ok(self.scene)
+	}
+}
+
+/// Watches a scene file, and the heightmaps, meshes and grids it references, for changes by polling their
+/// modification times
+pub struct SceneWatcher {
+	path: PathBuf,
+	modified: Option<SystemTime>,
+	/// The files the scene last loaded references, and when they were last seen modified
+	files: Vec<(PathBuf, Option<SystemTime>)>
+}
+
+impl SceneWatcher {
+	pub fn new<P: AsRef<Path>>(path: P) -> Self {
+		let path = path.as_ref().to_path_buf();
+		let modified = modified_time(&path);
+
+		SceneWatcher {
+			path,
+			modified,
+			files: Vec::new()
+		}
+	}
+
+	/// Loads the scene, watching the files it references from then on
+	pub fn load(&mut self) -> Result<SceneDesc, SceneError> {
+		let scene = SceneDesc::load(&self.path)?;
+		let mut files = Vec::new();
+		referenced_files(&scene.shapes, &mut files);
+		self.files = files.into_iter().map(|path| {
+			let modified = modified_time(&path);
+			(path, modified)
+		}).collect();
+
+		Ok(scene)
+	}
+
+	/// Returns the reloaded scene if the scene file or a file it references has been modified since the last poll,
+	/// along with the referenced files that were, as whatever was loaded from them needs reloading too. Returns
+	/// None if nothing has been modified
+	pub fn poll(&mut self) -> Option<(Result<SceneDesc, SceneError>, Vec<PathBuf>)> {
+		// Files may briefly not exist while an editor is saving them, so just try again next poll
+		let modified = modified_time(&self.path)?;
+		let mut changed_files = Vec::new();
+		for (path, file_modified) in &mut self.files {
+			if let Some(time) = modified_time(path).filter(|&time| *file_modified != Some(time)) {
+				*file_modified = Some(time);
+				changed_files.push(path.clone());
+			}
+		}
+		if self.modified == Some(modified) && changed_files.is_empty() {
+			return None;
+		}
+		self.modified = Some(modified);
+
+		Some((self.load(), changed_files))
+	}
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+	fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{*, super::shape::ShapeKind};
+
+	const CAMERA: &str = "camera: (position: (0., 0., 10.), look_at: (0., 0., 0.), canvas_dist: 10.)";
+
+	#[test]
+	fn example_scenes_load() {
+		let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes");
+		for entry in fs::read_dir(dir).unwrap() {
+			let path = entry.unwrap().path();
+			if path.extension().and_then(|e| e.to_str()) == Some("ron") {
+				if let Err(e) = SceneDesc::load(&path) {
+					panic!("{}: {}", path.display(), e);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn shapes_parse_without_extra_parentheses() {
+		let scene = SceneDesc::parse(&format!("Scene({}, shapes: [Shape(kind: Sphere, position: (1., 2., 3.))])", CAMERA)).unwrap();
+		assert_eq!(scene.shapes, vec![ShapeDesc::new(ShapeKind::Sphere).position([1., 2., 3.]).into()]);
+	}
+
+	#[test]
+	fn invalid_scenes_are_rejected() {
+		let parse = |shapes: &str| SceneDesc::parse(&format!("Scene({}, shapes: [{}])", CAMERA, shapes));
+		assert!(matches!(parse("Shape(kind: Spheroid)"), Err(SceneError::Parse(_))));
+		assert!(matches!(parse("Shape(kind: Sphere, size: (1., 2., 1.))"), Err(SceneError::Shape(0, _))));
+		assert!(matches!(parse("Shape(kind: Sphere), Group(children: [])"), Err(SceneError::EmptyGroup)));
+		assert!(matches!(parse("Shape(kind: Sphere, material: Some(0))"), Err(SceneError::UnknownMaterial(0, 0))));
+	}
+
+	#[test]
+	fn paths_are_relative_to_the_scene_file() {
+		let dir = std::env::temp_dir().join(format!("vulkano_raymarching_scene_{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let path = dir.join("scene.ron");
+		let shapes = "Group(children: [Shape(kind: Heightmap, heightmap: Some(\"maps/terrain.png\"))]), Shape(kind: Baked, grid: Some(\"/grids/baked.sdf\"))";
+		fs::write(&path, format!("Scene({}, shapes: [{}])", CAMERA, shapes)).unwrap();
+		let scene = SceneDesc::load(&path);
+		fs::remove_dir_all(&dir).unwrap();
+
+		let scene = scene.unwrap();
+		match &scene.shapes[0] {
+			CsgNode::Group(group) => match &group.children[0] {
+				CsgNode::Shape(shape) => assert_eq!(shape.heightmap, Some(dir.join("maps/terrain.png"))),
+				node => panic!("expected a shape, got {:?}", node)
+			},
+			node => panic!("expected a group, got {:?}", node)
+		}
+		// Absolute paths are left as they are
+		match &scene.shapes[1] {
+			CsgNode::Shape(shape) => assert_eq!(shape.grid, Some(PathBuf::from("/grids/baked.sdf"))),
+			node => panic!("expected a shape, got {:?}", node)
+		}
+	}
+
+	#[test]
+	fn watcher_reports_changed_referenced_files() {
+		let dir = std::env::temp_dir().join(format!("vulkano_raymarching_watcher_{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let path = dir.join("scene.ron");
+		let grid = dir.join("baked.sdf");
+		fs::write(&path, format!("Scene({}, shapes: [Shape(kind: Baked, grid: Some(\"baked.sdf\"))])", CAMERA)).unwrap();
+		fs::write(&grid, []).unwrap();
+
+		let mut watcher = SceneWatcher::new(&path);
+		let loaded = watcher.load().is_ok();
+		let unchanged = watcher.poll().is_none();
+		fs::File::options().write(true).open(&grid).unwrap().set_modified(SystemTime::UNIX_EPOCH).unwrap();
+		let changed = watcher.poll().map(|(scene, files)| (scene.is_ok(), files));
+		let unchanged_again = watcher.poll().is_none();
+		fs::remove_dir_all(&dir).unwrap();
+
+		assert!(loaded && unchanged && unchanged_again);
+		assert_eq!(changed, Some((true, vec![grid])));
+	}
+}
@@ -0,0 +1,242 @@
+use std::{fmt, fs, io::{self, Read, Write, BufReader, BufWriter}, path::{Path, PathBuf}};
+
+use super::{mesh::Mesh, scene::SceneError, export::MAX_GRID_RESOLUTION};
+
+/// The first bytes of a grid file
+const GRID_MAGIC: &[u8; 4] = b"SDFG";
+/// The version of the grid file format written, bumped whenever it changes
+const GRID_VERSION: u32 = 1;
+/// The length of a grid file's magic bytes and header, before the distances
+const GRID_HEADER_LEN: u64 = 36;
+
+#[derive(Debug)]
+pub enum GridError {
+	Io(io::Error),
+	/// The file doesn't start with the grid file magic bytes
+	NotAGrid,
+	/// The file is in a later version of the format than this build reads
+	UnsupportedVersion(u32),
+	/// The file's header is invalid, with a message saying why
+	InvalidHeader(&'static str),
+	/// The file's size is too small to sample or too large to have been baked along some axis, with the size it has
+	InvalidSize([u32; 3]),
+	/// A distance is NaN or infinite
+	NonFinite
+}
+
+impl fmt::Display for GridError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			GridError::Io(e) => write!(f, "Failed to read or write grid file: {}", e),
+			GridError::NotAGrid => write!(f, "File isn't an SDF grid file"),
+			GridError::UnsupportedVersion(version) => write!(f, "Grid file is version {}, but only versions up to {} are supported", version, GRID_VERSION),
+			GridError::InvalidHeader(msg) => write!(f, "Grid file has an invalid header: {}", msg),
+			GridError::InvalidSize(size) => write!(f, "Grid file must have between 2 and {} points along each axis, but has size {:?}", MAX_GRID_RESOLUTION, size),
+			GridError::NonFinite => write!(f, "Grid file has a non-finite distance")
+		}
+	}
+}
+
+impl std::error::Error for GridError {}
+
+impl From<io::Error> for GridError {
+	fn from(e: io::Error) -> Self {
+		GridError::Io(e)
+	}
+}
+
+/// Signed distances sampled at the points of a regular grid, negative inside. Point (x, y, z) is at
+/// `origin + (x, y, z) * cell_size`, and its distance is at index `x + size.x * (y + size.y * z)`
+///
+/// Grids are saved as binary files, all little endian:
+///
+/// | Offset | Type       | Contents                                        |
+/// |--------|------------|-------------------------------------------------|
+/// | 0      | `[u8; 4]`  | The magic bytes `SDFG`                          |
+/// | 4      | `u32`      | The format version, currently 1                 |
+/// | 8      | `[u32; 3]` | `size`, the number of points along x, y and z   |
+/// | 20     | `[f32; 3]` | `origin`, the position of the first point       |
+/// | 32     | `f32`      | `cell_size`, the distance between points        |
+/// | 36     | `[f32]`    | The distances, `size.x * size.y * size.z` of them, in the order above |
+#[derive(Debug, Clone, PartialEq)]
+pub struct SdfGrid {
+	size: [u32; 3],
+	origin: [f32; 3],
+	cell_size: f32,
+	distances: Vec<f32>
+}
+
+impl SdfGrid {
+	/// Panics if `distances` doesn't have a distance for every point
+	pub fn new(size: [u32; 3], origin: [f32; 3], cell_size: f32, distances: Vec<f32>) -> Self {
+		assert_eq!(distances.len(), size.iter().product::<u32>() as usize, "SDF grid has the wrong number of distances");
+		SdfGrid { size, origin, cell_size, distances }
+	}
+
+	/// The number of points along each axis
+	pub fn size(&self) -> [u32; 3] {
+		self.size
+	}
+
+	/// The position of the first point
+	pub fn origin(&self) -> [f32; 3] {
+		self.origin
+	}
+
+	/// The distance between neighbouring points
+	pub fn cell_size(&self) -> f32 {
+		self.cell_size
+	}
+
+	pub fn distances(&self) -> &[f32] {
+		&self.distances
+	}
+
+	/// Loads a grid file, see [`SdfGrid`] for the format
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, GridError> {
+		let file = fs::File::open(path)?;
+		let file_len = file.metadata()?.len();
+		let mut reader = BufReader::new(file);
+
+		let mut magic = [0; 4];
+		reader.read_exact(&mut magic)?;
+		if &magic != GRID_MAGIC {
+			return Err(GridError::NotAGrid);
+		}
+
+		let mut header = [0; 32];
+		reader.read_exact(&mut header)?;
+		let word = |i: usize| header[i * 4..i * 4 + 4].try_into().unwrap();
+		let version = u32::from_le_bytes(word(0));
+		if version > GRID_VERSION {
+			return Err(GridError::UnsupportedVersion(version));
+		}
+		let size = [1, 2, 3].map(|i| u32::from_le_bytes(word(i)));
+		let origin = [4, 5, 6].map(|i| f32::from_le_bytes(word(i)));
+		let cell_size = f32::from_le_bytes(word(7));
+
+		// The largest grids that can be baked, so a corrupt size can't exhaust memory
+		if size.iter().any(|&s| !(2..=MAX_GRID_RESOLUTION).contains(&s)) {
+			return Err(GridError::InvalidSize(size));
+		}
+		let points = size.iter().map(|&s| s as u64).product::<u64>();
+		if file_len != GRID_HEADER_LEN + points * 4 {
+			return Err(GridError::InvalidHeader("size doesn't match the length of the file"));
+		}
+		if !origin.iter().all(|v| v.is_finite()) || !cell_size.is_finite() || cell_size <= 0. {
+			return Err(GridError::InvalidHeader("origin must be finite and cell size positive"));
+		}
+
+		let mut bytes = vec![0; points as usize * 4];
+		reader.read_exact(&mut bytes)?;
+		let distances: Vec<f32> = bytes.chunks_exact(4).map(|d| f32::from_le_bytes(d.try_into().unwrap())).collect();
+		if !distances.iter().all(|d| d.is_finite()) {
+			return Err(GridError::NonFinite);
+		}
+
+		Ok(SdfGrid { size, origin, cell_size, distances })
+	}
+
+	/// Saves the grid as a grid file, see [`SdfGrid`] for the format
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), GridError> {
+		let mut writer = BufWriter::new(fs::File::create(path)?);
+
+		writer.write_all(GRID_MAGIC)?;
+		writer.write_all(&GRID_VERSION.to_le_bytes())?;
+		for s in self.size {
+			writer.write_all(&s.to_le_bytes())?;
+		}
+		for v in self.origin.iter().chain([&self.cell_size]).chain(&self.distances) {
+			writer.write_all(&v.to_le_bytes())?;
+		}
+		writer.flush()?;
+
+		Ok(())
+	}
+}
+
+/// Where a volume a scene's shapes sample comes from, see [`super::ShapeKind::Mesh`] and [`super::ShapeKind::Baked`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum VolumeSource {
+	/// An OBJ or STL file, voxelized with `resolution` points along the longest side of its grid
+	Mesh {
+		path: PathBuf,
+		resolution: u32
+	},
+	/// A grid file, see [`SdfGrid`]
+	Grid(PathBuf)
+}
+
+impl VolumeSource {
+	/// The file the volume is loaded from
+	pub fn path(&self) -> &Path {
+		match self {
+			VolumeSource::Mesh { path, .. } | VolumeSource::Grid(path) => path
+		}
+	}
+
+	pub fn load(&self) -> Result<SdfGrid, SceneError> {
+		match self {
+			VolumeSource::Mesh { path, resolution } => Mesh::load(path)
+				.and_then(|mesh| mesh.to_sdf_grid(*resolution))
+				.map_err(|e| SceneError::Mesh(path.clone(), e)),
+			VolumeSource::Grid(path) => SdfGrid::load(path).map_err(|e| SceneError::Grid(path.clone(), e))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A path in the temp directory unique to the test, so tests running at once don't share files
+	fn temp_path(name: &str) -> PathBuf {
+		std::env::temp_dir().join(format!("vulkano_raymarching_{}_{}.sdf", name, std::process::id()))
+	}
+
+	fn test_grid() -> SdfGrid {
+		let distances = (0..2 * 3 * 4).map(|i| i as f32 * 0.25 - 2.).collect();
+		SdfGrid::new([2, 3, 4], [-1., 0.5, 2.], 0.125, distances)
+	}
+
+	#[test]
+	fn saved_grid_loads_unchanged() {
+		let path = temp_path("round_trip");
+		let grid = test_grid();
+		grid.save(&path).unwrap();
+		let loaded = SdfGrid::load(&path);
+		fs::remove_file(&path).unwrap();
+
+		assert_eq!(loaded.unwrap(), grid);
+	}
+
+	#[test]
+	fn grid_file_shorter_than_its_size_is_rejected() {
+		let path = temp_path("truncated");
+		test_grid().save(&path).unwrap();
+		let bytes = fs::read(&path).unwrap();
+		fs::write(&path, &bytes[..bytes.len() - 4]).unwrap();
+		let loaded = SdfGrid::load(&path);
+		fs::remove_file(&path).unwrap();
+
+		assert!(matches!(loaded, Err(GridError::InvalidHeader(_))));
+	}
+
+	#[test]
+	fn grid_file_too_large_to_bake_is_rejected() {
+		let path = temp_path("too_large");
+		let mut bytes = Vec::new();
+		bytes.extend_from_slice(GRID_MAGIC);
+		for word in [GRID_VERSION, 2, 2, MAX_GRID_RESOLUTION + 1] {
+			bytes.extend_from_slice(&word.to_le_bytes());
+		}
+		for v in [0f32, 0., 0., 1.] {
+			bytes.extend_from_slice(&v.to_le_bytes());
+		}
+		fs::write(&path, &bytes).unwrap();
+		let loaded = SdfGrid::load(&path);
+		fs::remove_file(&path).unwrap();
+
+		assert!(matches!(loaded, Err(GridError::InvalidSize(_))));
+	}
+}
@@ -0,0 +1,869 @@
+use std::{borrow::Cow, fmt, path::PathBuf};
+
+use serde::Deserialize;
+
+use super::{shaders::ray_marching_shader::ty::Shape, palette::{PaletteDesc, PaletteError}, fractal::{FractalOpDesc, HybridStep, MAX_FRACTAL_OPS}, domain::DomainOp, modifier::{ModifierDesc, MAX_MODIFIERS}, mesh::{MIN_MESH_RESOLUTION, MAX_MESH_RESOLUTION}, SHAPE_TYPE_SPHERE, SHAPE_TYPE_WOBBLY_SPHERE, SHAPE_TYPE_MANDELBULB, SHAPE_TYPE_BOX, SHAPE_TYPE_ROUNDED_BOX, SHAPE_TYPE_TORUS, SHAPE_TYPE_CAPSULE, SHAPE_TYPE_CYLINDER, SHAPE_TYPE_CONE, SHAPE_TYPE_PLANE, SHAPE_TYPE_ELLIPSOID, SHAPE_TYPE_HEX_PRISM, SHAPE_TYPE_MANDELBOX, SHAPE_TYPE_MENGER_SPONGE, SHAPE_TYPE_SIERPINSKI_TETRAHEDRON, SHAPE_TYPE_QUATERNION_JULIA, SHAPE_TYPE_KIFS, SHAPE_TYPE_HYBRID, SHAPE_TYPE_GYROID, SHAPE_TYPE_SCHWARZ_P, SHAPE_TYPE_SCHWARZ_D, SHAPE_TYPE_HEIGHTMAP, SHAPE_TYPE_MESH, SHAPE_TYPE_BAKED, BLEND_MODE_NONE, BLEND_MODE_INTERSECTION, BLEND_MODE_DIFFERENCE, BLEND_MODE_SMOOTH};
+
+/// The most iterations a fractal shape can run per distance estimate, to keep the shader from stalling
+const MAX_FRACTAL_ITERATIONS: u32 = 256;
+
+/// The type of primitive a shape is. Unless noted otherwise, shapes are centred on their position, and
+/// those with an axis (e.g. cylinders) are aligned with the y axis before rotation
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum ShapeKind {
+	/// Size is the radius, on every axis
+	Sphere,
+	/// Size is the radius, on every axis. A sphere with a [`ModifierDesc::SineDisplacement`] is more flexible
+	WobblySphere,
+	/// Size scales the fractal, which fits within a radius of about 1.2 at size 1 and power 8
+	Mandelbulb {
+		#[serde(default = "default_mandelbulb_power")]
+		power: f32,
+		#[serde(default = "default_fractal_iterations")]
+		iterations: u32,
+		/// Points further than this from the centre are treated as having escaped
+		#[serde(default = "default_fractal_bailout")]
+		bailout: f32
+	},
+	/// Size is the half extents
+	Box,
+	/// Size is the half extents, including the rounded corners
+	RoundedBox {
+		radius: f32
+	},
+	/// Size x is the radius of the ring and size y the radius of the tube. Lies in the xz plane
+	Torus,
+	/// Size x is the radius and size y the half height of the line segment the capsule is around
+	Capsule,
+	/// Size x is the radius and size y the half height
+	Cylinder,
+	/// Size x is the radius of the base and size y the half height. A top radius above 0 cuts the cone off
+	Cone {
+		#[serde(default)]
+		top_radius: f32
+	},
+	/// An infinite plane through the position, facing up the y axis. Size is ignored
+	Plane,
+	/// Size is the radius along each axis
+	Ellipsoid,
+	/// Size x is the distance from the centre to each flat side and size y the half height
+	HexPrism,
+	/// Size scales the fractal, which is several units across at size 1 (about 6 from the centre at scale 2)
+	Mandelbox {
+		/// Negative scales give quite different shapes to positive ones, but must be at least 1 either way
+		#[serde(default = "default_mandelbox_scale")]
+		scale: f32,
+		/// Points closer to the centre than this are scaled up by a fixed amount by the sphere fold
+		#[serde(default = "default_mandelbox_min_radius")]
+		min_radius: f32,
+		/// Points closer to the centre than this are inverted through this sphere by the sphere fold
+		#[serde(default = "default_mandelbox_fixed_radius")]
+		fixed_radius: f32,
+		#[serde(default = "default_fractal_iterations")]
+		iterations: u32
+	},
+	/// Size is the half extents of the sponge's cube, on every axis
+	MengerSponge {
+		#[serde(default = "default_menger_iterations")]
+		iterations: u32,
+		/// How much smaller each level of cubes is than the last. 3 gives the classic sponge
+		#[serde(default = "default_menger_scale")]
+		scale: f32,
+		/// Where the corner cube of each level is centred, relative to its parent. (1, 1, 1) gives the classic sponge
+		#[serde(default = "default_menger_offset")]
+		offset: [f32; 3]
+	},
+	/// Size scales the fractal, which has its corners at (1, 1, 1), (-1, -1, 1), (1, -1, -1) and (-1, 1, -1) at size 1
+	SierpinskiTetrahedron {
+		#[serde(default = "default_sierpinski_iterations")]
+		iterations: u32,
+		/// How much smaller each level of tetrahedra is than the last. 2 gives the classic tetrahedron
+		#[serde(default = "default_sierpinski_scale")]
+		scale: f32
+	},
+	/// The 3D slice (w = 0) of the quaternion Julia set for the constant c (x, y, z, w). Size scales the fractal,
+	/// which fits within a radius of about 1.5 at size 1
+	QuaternionJulia {
+		#[serde(default = "default_julia_c")]
+		c: [f32; 4],
+		#[serde(default = "default_julia_iterations")]
+		iterations: u32,
+		/// Points further than this from the centre are treated as having escaped
+		#[serde(default = "default_julia_bailout")]
+		bailout: f32
+	},
+	/// A kaleidoscopic IFS fractal, made by running the shape's [`ShapeDesc::ops`] on each point every iteration, then
+	/// measuring the distance to the centre. Size scales the fractal
+	Kifs {
+		#[serde(default = "default_fractal_iterations")]
+		iterations: u32,
+		/// Points further than this from the centre are treated as having escaped
+		#[serde(default = "default_kifs_bailout")]
+		bailout: f32
+	},
+	/// An escape time fractal alternating between formulas, following the shape's [`ShapeDesc::schedule`]. Size scales the fractal
+	Hybrid {
+		#[serde(default = "default_fractal_iterations")]
+		iterations: u32,
+		/// Points further than this from the centre are treated as having escaped
+		#[serde(default = "default_hybrid_bailout")]
+		bailout: f32
+	},
+	/// A gyroid lattice, filling all of space like a plane. Size is the size of a cell along each axis, and `thickness`
+	/// the width of the walls. Intersect it with another shape to fill that shape with the lattice, see [`BlendMode`]
+	Gyroid {
+		#[serde(default = "default_tpms_thickness")]
+		thickness: f32
+	},
+	/// A Schwarz P (primitive) lattice, of tubes along each axis meeting at the cell corners. Sized like [`ShapeKind::Gyroid`]
+	SchwarzP {
+		#[serde(default = "default_tpms_thickness")]
+		thickness: f32
+	},
+	/// A Schwarz D (diamond) lattice. Sized like [`ShapeKind::Gyroid`]
+	SchwarzD {
+		#[serde(default = "default_tpms_thickness")]
+		thickness: f32
+	},
+	/// Terrain raised from the grayscale image at [`ShapeDesc::heightmap`], solid below its surface like a plane. Size x
+	/// and z are the half extents of the terrain, and size y the height of white above the shape's position
+	Heightmap,
+	/// The triangle mesh in the OBJ or STL file at [`ShapeDesc::mesh`], voxelized into a grid of signed distances with
+	/// `resolution` points along its longest side. The mesh should be closed. It's scaled so the longest side of its
+	/// bounding box is twice the size, and centred on the shape's position
+	Mesh {
+		#[serde(default = "default_mesh_resolution")]
+		resolution: u32
+	},
+	/// A grid of signed distances loaded from the grid file at [`ShapeDesc::grid`], such as the scene baked by
+	/// [`super::Raymarch::bake`]. Size scales the grid, centred on the shape's position, so a size of 1 is the size it
+	/// was baked at. Cheap to evaluate however costly the baked shapes were, e.g. to repeat a fractal many times
+	Baked
+}
+
+impl ShapeKind {
+	/// A Mandelbulb with the given power, and the default iteration count and bailout
+	pub fn mandelbulb(power: f32) -> Self {
+		ShapeKind::Mandelbulb {
+			power,
+			iterations: default_fractal_iterations(),
+			bailout: default_fractal_bailout()
+		}
+	}
+
+	/// A Mandelbox with the given scale, and the default radii and iteration count
+	pub fn mandelbox(scale: f32) -> Self {
+		ShapeKind::Mandelbox {
+			scale,
+			min_radius: default_mandelbox_min_radius(),
+			fixed_radius: default_mandelbox_fixed_radius(),
+			iterations: default_fractal_iterations()
+		}
+	}
+
+	/// The classic Menger sponge, with the given number of levels of holes
+	pub fn menger_sponge(iterations: u32) -> Self {
+		ShapeKind::MengerSponge {
+			iterations,
+			scale: default_menger_scale(),
+			offset: default_menger_offset()
+		}
+	}
+
+	/// The classic Sierpinski tetrahedron, with the given number of levels of holes
+	pub fn sierpinski_tetrahedron(iterations: u32) -> Self {
+		ShapeKind::SierpinskiTetrahedron {
+			iterations,
+			scale: default_sierpinski_scale()
+		}
+	}
+
+	/// A quaternion Julia set for the constant c (x, y, z, w), with the default iteration count and bailout
+	pub fn quaternion_julia(c: [f32; 4]) -> Self {
+		ShapeKind::QuaternionJulia {
+			c,
+			iterations: default_julia_iterations(),
+			bailout: default_julia_bailout()
+		}
+	}
+
+	fn id(self) -> u32 {
+		match self {
+			ShapeKind::Sphere => SHAPE_TYPE_SPHERE,
+			ShapeKind::WobblySphere => SHAPE_TYPE_WOBBLY_SPHERE,
+			ShapeKind::Mandelbulb { .. } => SHAPE_TYPE_MANDELBULB,
+			ShapeKind::Box => SHAPE_TYPE_BOX,
+			ShapeKind::RoundedBox { .. } => SHAPE_TYPE_ROUNDED_BOX,
+			ShapeKind::Torus => SHAPE_TYPE_TORUS,
+			ShapeKind::Capsule => SHAPE_TYPE_CAPSULE,
+			ShapeKind::Cylinder => SHAPE_TYPE_CYLINDER,
+			ShapeKind::Cone { .. } => SHAPE_TYPE_CONE,
+			ShapeKind::Plane => SHAPE_TYPE_PLANE,
+			ShapeKind::Ellipsoid => SHAPE_TYPE_ELLIPSOID,
+			ShapeKind::HexPrism => SHAPE_TYPE_HEX_PRISM,
+			ShapeKind::Mandelbox { .. } => SHAPE_TYPE_MANDELBOX,
+			ShapeKind::MengerSponge { .. } => SHAPE_TYPE_MENGER_SPONGE,
+			ShapeKind::SierpinskiTetrahedron { .. } => SHAPE_TYPE_SIERPINSKI_TETRAHEDRON,
+			ShapeKind::QuaternionJulia { .. } => SHAPE_TYPE_QUATERNION_JULIA,
+			ShapeKind::Kifs { .. } => SHAPE_TYPE_KIFS,
+			ShapeKind::Hybrid { .. } => SHAPE_TYPE_HYBRID,
+			ShapeKind::Gyroid { .. } => SHAPE_TYPE_GYROID,
+			ShapeKind::SchwarzP { .. } => SHAPE_TYPE_SCHWARZ_P,
+			ShapeKind::SchwarzD { .. } => SHAPE_TYPE_SCHWARZ_D,
+			ShapeKind::Heightmap => SHAPE_TYPE_HEIGHTMAP,
+			ShapeKind::Mesh { .. } => SHAPE_TYPE_MESH,
+			ShapeKind::Baked => SHAPE_TYPE_BAKED
+		}
+	}
+
+	/// The shape's parameters beyond its size, as laid out in the shader's Shape::params. The range of a Kifs or
+	/// Hybrid shape's ops, a Heightmap shape's layer and a Mesh or Baked shape's grid are left for the caller to fill in, as they depend on where the
+	/// shape is in the scene
+	fn params(self) -> [[f32; 4]; 2] {
+		match self {
+			ShapeKind::Mandelbulb { power, iterations, bailout } => [[power, iterations as f32, bailout, 0.], [0.; 4]],
+			ShapeKind::RoundedBox { radius } => [[radius, 0., 0., 0.], [0.; 4]],
+			ShapeKind::Cone { top_radius } => [[top_radius, 0., 0., 0.], [0.; 4]],
+			ShapeKind::Mandelbox { scale, min_radius, fixed_radius, iterations } => [[scale, min_radius, fixed_radius, iterations as f32], [0.; 4]],
+			ShapeKind::MengerSponge { iterations, scale, offset } => [[iterations as f32, scale, 0., 0.], [offset[0], offset[1], offset[2], 0.]],
+			ShapeKind::SierpinskiTetrahedron { iterations, scale } => [[iterations as f32, scale, 0., 0.], [0.; 4]],
+			ShapeKind::QuaternionJulia { c, iterations, bailout } => [c, [iterations as f32, bailout, 0., 0.]],
+			ShapeKind::Kifs { iterations, bailout } | ShapeKind::Hybrid { iterations, bailout } => [[iterations as f32, bailout, 0., 0.], [0.; 4]],
+			ShapeKind::Gyroid { thickness } | ShapeKind::SchwarzP { thickness } | ShapeKind::SchwarzD { thickness } => [[thickness, 0., 0., 0.], [0.; 4]],
+			_ => [[0.; 4]; 2]
+		}
+	}
+
+	/// The number of iterations a fractal shape runs, or None if the shape isn't a fractal
+	fn iterations(self) -> Option<u32> {
+		match self {
+			ShapeKind::Mandelbulb { iterations, .. }
+			| ShapeKind::Mandelbox { iterations, .. }
+			| ShapeKind::MengerSponge { iterations, .. }
+			| ShapeKind::SierpinskiTetrahedron { iterations, .. }
+			| ShapeKind::QuaternionJulia { iterations, .. }
+			| ShapeKind::Kifs { iterations, .. }
+			| ShapeKind::Hybrid { iterations, .. } => Some(iterations),
+			_ => None
+		}
+	}
+
+	/// Roughly how far the shape reaches from its centre, as a multiple of its size
+	fn extent(self) -> f32 {
+		match self {
+			ShapeKind::Mandelbox { .. } => 6.,
+			_ if self.is_fractal() => 1.5,
+			_ => 1.
+		}
+	}
+
+	/// Whether the shape carries on forever, or far enough that it might as well
+	fn is_unbounded(self) -> bool {
+		matches!(self, ShapeKind::Plane | ShapeKind::Gyroid { .. } | ShapeKind::SchwarzP { .. } | ShapeKind::SchwarzD { .. } | ShapeKind::Heightmap)
+	}
+
+	/// Whether the shape outputs iteration data that it can be coloured by, see [`PaletteDesc`]
+	fn is_fractal(self) -> bool {
+		self.iterations().is_some()
+	}
+
+	/// Whether the shape can only be scaled by the same amount on every axis
+	fn needs_uniform_size(self) -> bool {
+		self.is_fractal() || matches!(self, ShapeKind::Sphere | ShapeKind::WobblySphere | ShapeKind::Mesh { .. } | ShapeKind::Baked)
+	}
+
+	fn validate_params(self, size: [f32; 3]) -> Result<(), ShapeError> {
+		if !self.params().iter().flatten().all(|p| p.is_finite()) {
+			return Err(ShapeError::NonFinite(self));
+		}
+		if let Some(iterations) = self.iterations() {
+			if iterations == 0 || iterations > MAX_FRACTAL_ITERATIONS {
+				return Err(ShapeError::IterationCount(self, iterations));
+			}
+		}
+
+		match self {
+			ShapeKind::Mandelbulb { power, .. } if power < 2. => {
+				Err(ShapeError::InvalidParameter(self, "power must be at least 2"))
+			},
+			ShapeKind::Mandelbulb { bailout, .. } | ShapeKind::QuaternionJulia { bailout, .. } | ShapeKind::Kifs { bailout, .. } | ShapeKind::Hybrid { bailout, .. } if bailout <= 1. => {
+				Err(ShapeError::InvalidParameter(self, "bailout must be greater than 1"))
+			},
+			ShapeKind::Mandelbox { scale, .. } if scale.abs() < 1. => {
+				Err(ShapeError::InvalidParameter(self, "scale must be at least 1 or at most -1"))
+			},
+			ShapeKind::Mandelbox { min_radius, fixed_radius, .. } if min_radius <= 0. || fixed_radius < min_radius => {
+				Err(ShapeError::InvalidParameter(self, "min radius must be positive and no greater than fixed radius"))
+			},
+			ShapeKind::MengerSponge { scale, .. } | ShapeKind::SierpinskiTetrahedron { scale, .. } if scale <= 1. => {
+				Err(ShapeError::InvalidParameter(self, "scale must be greater than 1"))
+			},
+			ShapeKind::RoundedBox { radius } if radius < 0. || radius > size[0].min(size[1]).min(size[2]) => {
+				Err(ShapeError::InvalidParameter(self, "radius must be between 0 and the smallest half extent"))
+			},
+			ShapeKind::Cone { top_radius } if top_radius < 0. => {
+				Err(ShapeError::InvalidParameter(self, "top radius must not be negative"))
+			},
+			ShapeKind::Gyroid { thickness } | ShapeKind::SchwarzP { thickness } | ShapeKind::SchwarzD { thickness } if thickness <= 0. || thickness >= size[0].min(size[1]).min(size[2]) => {
+				Err(ShapeError::InvalidParameter(self, "thickness must be positive and less than the smallest cell size"))
+			},
+			ShapeKind::Mesh { resolution } if !(MIN_MESH_RESOLUTION..=MAX_MESH_RESOLUTION).contains(&resolution) => {
+				Err(ShapeError::MeshResolution(self, resolution))
+			},
+			_ => Ok(())
+		}
+	}
+}
+
+/// How a shape or group is combined with the nodes before it in the scene, see [`super::CsgNode`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum BlendMode {
+	#[default]
+	Union,
+	Intersection,
+	/// Carves the node out of the nodes before it
+	Difference,
+	/// Union that smoothly merges the nodes, over a distance given by the blend strength
+	Smooth
+}
+
+impl BlendMode {
+	pub(super) fn id(self) -> u32 {
+		match self {
+			BlendMode::Union => BLEND_MODE_NONE,
+			BlendMode::Intersection => BLEND_MODE_INTERSECTION,
+			BlendMode::Difference => BLEND_MODE_DIFFERENCE,
+			BlendMode::Smooth => BLEND_MODE_SMOOTH
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShapeError {
+	NonFinite(ShapeKind),
+	/// The rotation quaternion is zero, or the scale isn't positive
+	InvalidTransform(ShapeKind),
+	NonPositiveBlendStrength(ShapeKind, f32),
+	NonPositiveSize(ShapeKind, [f32; 3]),
+	NonUniformSize(ShapeKind, [f32; 3]),
+	/// A parameter specific to the kind of shape is out of range, with a message saying why
+	InvalidParameter(ShapeKind, &'static str),
+	Palette(ShapeKind, PaletteError),
+	/// The fractal op at the given index is invalid, with a message saying why
+	InvalidOp(ShapeKind, usize, &'static str),
+	/// The hybrid step at the given index is invalid, with a message saying why
+	InvalidStep(ShapeKind, usize, &'static str),
+	/// The domain operator is invalid, with a message saying why
+	InvalidDomain(ShapeKind, &'static str),
+	/// The modifier at the given index is invalid, with a message saying why
+	InvalidModifier(ShapeKind, usize, Cow<'static, str>),
+	/// A fractal shape has no iterations or more than the shader supports, with the number it has
+	IterationCount(ShapeKind, u32),
+	/// A Mesh shape's resolution is out of range, with the resolution it has
+	MeshResolution(ShapeKind, u32),
+	/// A Kifs shape has no ops or more than the shader supports, with the number it has
+	OpCount(ShapeKind, usize),
+	/// A Hybrid shape's schedule has no iterations or more in total than the shader supports, with the number it has
+	ScheduleLength(ShapeKind, usize),
+	/// The shape has more modifiers than the shader supports, with the number it has
+	ModifierCount(ShapeKind, usize)
+}
+
+impl fmt::Display for ShapeError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ShapeError::NonFinite(kind) => write!(f, "{:?} has a non-finite transform, size, albedo or blend strength", kind),
+			ShapeError::InvalidTransform(kind) => write!(f, "{:?} must have a non-zero rotation and a positive scale", kind),
+			ShapeError::NonPositiveBlendStrength(kind, strength) => write!(f, "{:?} is smoothly blended so must have a positive blend strength, but has blend strength {}", kind, strength),
+			ShapeError::NonPositiveSize(kind, size) => write!(f, "{:?} must have a positive size, but has size {:?}", kind, size),
+			ShapeError::NonUniformSize(kind, size) => write!(f, "{:?} must have the same size on every axis, but has size {:?}", kind, size),
+			ShapeError::InvalidParameter(kind, msg) => write!(f, "{:?} is invalid: {}", kind, msg),
+			ShapeError::Palette(kind, e) => write!(f, "{:?} has an invalid palette: {}", kind, e),
+			ShapeError::InvalidOp(kind, i, msg) => write!(f, "{:?} has an invalid op at index {}: {}", kind, i, msg),
+			ShapeError::InvalidStep(kind, i, msg) => write!(f, "{:?} has an invalid step at index {}: {}", kind, i, msg),
+			ShapeError::InvalidDomain(kind, msg) => write!(f, "{:?} has an invalid domain operator: {}", kind, msg),
+			ShapeError::InvalidModifier(kind, i, msg) => write!(f, "{:?} has an invalid modifier at index {}: {}", kind, i, msg),
+			ShapeError::IterationCount(kind, count) => write!(f, "{:?} must have between 1 and {} iterations, but has {}", kind, MAX_FRACTAL_ITERATIONS, count),
+			ShapeError::MeshResolution(kind, resolution) => write!(f, "{:?} must have a resolution between {} and {}, but has resolution {}", kind, MIN_MESH_RESOLUTION, MAX_MESH_RESOLUTION, resolution),
+			ShapeError::OpCount(kind, count) => write!(f, "{:?} must have between 1 and {} ops, but has {}", kind, MAX_FRACTAL_OPS, count),
+			ShapeError::ScheduleLength(kind, count) => write!(f, "{:?} must have between 1 and {} iterations in its schedule in total, but has {}", kind, MAX_FRACTAL_OPS, count),
+			ShapeError::ModifierCount(kind, count) => write!(f, "{:?} can have at most {} modifiers, but has {}", kind, MAX_MODIFIERS, count)
+		}
+	}
+}
+
+impl std::error::Error for ShapeError {}
+
+/// A shape in a scene. Use [`ShapeDesc::to_shape`] to validate it and lay it out for the shader
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ShapeDesc {
+	pub kind: ShapeKind,
+	#[serde(default)]
+	pub position: [f32; 3],
+	/// Quaternion (x, y, z, w), normalised when the shape is laid out
+	#[serde(default = "default_rotation")]
+	pub rotation: [f32; 4],
+	#[serde(default = "default_scale")]
+	pub scale: [f32; 3],
+	/// Index of the shape or group (counting depth first, with groups counted before their children) that this
+	/// shape's transform is relative to. Shapes in a group can only use the group or other nodes in it, and chains of
+	/// parents can't loop. Defaults to the group the shape is in
+	#[serde(default)]
+	pub parent: Option<usize>,
+	/// The dimensions of the primitive, e.g. the radius of a sphere. Unlike the scale, this doesn't distort the shape
+	#[serde(default = "default_size")]
+	pub size: [f32; 3],
+	#[serde(default = "default_albedo")]
+	pub albedo: [f32; 3],
+	/// Colours a fractal shape by its iteration data rather than its albedo
+	#[serde(default)]
+	pub palette: Option<PaletteDesc>,
+	/// Index of the material in the scene's material table the shape is shaded with, see [`super::MaterialDesc`].
+	/// The material's albedo replaces the shape's
+	#[serde(default)]
+	pub material: Option<usize>,
+	/// The operations a [`ShapeKind::Kifs`] shape runs on each point every iteration, in order
+	#[serde(default)]
+	pub ops: Vec<FractalOpDesc>,
+	/// The formulas a [`ShapeKind::Hybrid`] shape runs, in order, repeating from the start once the last has run.
+	/// E.g. two Mandelbulb iterations then one Mandelbox iteration
+	#[serde(default)]
+	pub schedule: Vec<HybridStep>,
+	/// Repeats or mirrors the shape, see [`DomainOp`]
+	#[serde(default)]
+	pub domain: Option<DomainOp>,
+	/// Deforms the shape, see [`ModifierDesc`]
+	#[serde(default)]
+	pub modifiers: Vec<ModifierDesc>,
+	/// The grayscale image a [`ShapeKind::Heightmap`] shape's terrain is raised from. Relative to the scene file's
+	/// directory if the scene is loaded from a file, or the working directory otherwise
+	#[serde(default)]
+	pub heightmap: Option<PathBuf>,
+	/// The OBJ or STL file a [`ShapeKind::Mesh`] shape is voxelized from, relative to the same directory as [`ShapeDesc::heightmap`]
+	#[serde(default)]
+	pub mesh: Option<PathBuf>,
+	/// The grid file a [`ShapeKind::Baked`] shape renders, relative to the same directory as [`ShapeDesc::heightmap`]
+	#[serde(default)]
+	pub grid: Option<PathBuf>,
+	/// How the shape is combined with the nodes before it. Ignored for the first node in a group
+	#[serde(default)]
+	pub blend_mode: BlendMode,
+	#[serde(default = "default_blend_strength")]
+	pub blend_strength: f32
+}
+
+pub(super) fn default_rotation() -> [f32; 4] {
+	[0., 0., 0., 1.]
+}
+
+fn default_mandelbulb_power() -> f32 {
+	8.
+}
+
+fn default_fractal_iterations() -> u32 {
+	15
+}
+
+fn default_fractal_bailout() -> f32 {
+	2.
+}
+
+fn default_mandelbox_scale() -> f32 {
+	2.
+}
+
+fn default_mandelbox_min_radius() -> f32 {
+	0.5
+}
+
+fn default_mandelbox_fixed_radius() -> f32 {
+	1.
+}
+
+fn default_menger_iterations() -> u32 {
+	4
+}
+
+fn default_menger_scale() -> f32 {
+	3.
+}
+
+fn default_menger_offset() -> [f32; 3] {
+	[1.; 3]
+}
+
+fn default_sierpinski_iterations() -> u32 {
+	8
+}
+
+fn default_sierpinski_scale() -> f32 {
+	2.
+}
+
+fn default_julia_c() -> [f32; 4] {
+	[-0.2, 0.6, 0.2, 0.2]
+}
+
+fn default_julia_iterations() -> u32 {
+	11
+}
+
+fn default_julia_bailout() -> f32 {
+	4.
+}
+
+fn default_kifs_bailout() -> f32 {
+	100.
+}
+
+fn default_tpms_thickness() -> f32 {
+	0.1
+}
+
+fn default_mesh_resolution() -> u32 {
+	64
+}
+
+fn default_hybrid_bailout() -> f32 {
+	4.
+}
+
+pub(super) fn default_scale() -> [f32; 3] {
+	[1.; 3]
+}
+
+/// Creates a quaternion rotating by `angle` radians anticlockwise about `axis`
+pub fn axis_angle(axis: [f32; 3], angle: f32) -> [f32; 4] {
+	let len = (axis[0] * axis[0] + axis[1] * axis[1] + axis[2] * axis[2]).sqrt();
+	let s = (angle * 0.5).sin() / len;
+	[axis[0] * s, axis[1] * s, axis[2] * s, (angle * 0.5).cos()]
+}
+
+/// Returns the rotation normalised, or None if the rotation and scale can't be used in a transform
+pub(super) fn normalise_transform(rotation: [f32; 4], scale: [f32; 3]) -> Option<[f32; 4]> {
+	let len = rotation.iter().map(|r| r * r).sum::<f32>().sqrt();
+	if !len.is_finite() || len == 0. || scale.iter().any(|&s| s <= 0.) {
+		return None;
+	}
+	Some(rotation.map(|r| r / len))
+}
+
+/// Returns the vector normalised, or None if it's too short or long to have a direction
+pub(super) fn normalise(v: [f32; 3]) -> Option<[f32; 3]> {
+	let len = v.iter().map(|c| c * c).sum::<f32>().sqrt();
+	if !len.is_finite() || len == 0. {
+		return None;
+	}
+	Some(v.map(|c| c / len))
+}
+
+fn default_size() -> [f32; 3] {
+	[1.; 3]
+}
+
+fn default_albedo() -> [f32; 3] {
+	[1.; 3]
+}
+
+pub(super) fn default_blend_strength() -> f32 {
+	0.1
+}
+
+impl ShapeDesc {
+	/// Creates a shape of the given kind at the origin with a size of 1 and a white albedo
+	pub fn new(kind: ShapeKind) -> Self {
+		ShapeDesc {
+			kind,
+			position: [0.; 3],
+			rotation: default_rotation(),
+			scale: default_scale(),
+			parent: None,
+			size: default_size(),
+			albedo: default_albedo(),
+			palette: None,
+			material: None,
+			ops: Vec::new(),
+			schedule: Vec::new(),
+			domain: None,
+			modifiers: Vec::new(),
+			heightmap: None,
+			mesh: None,
+			grid: None,
+			blend_mode: BlendMode::default(),
+			blend_strength: default_blend_strength()
+		}
+	}
+
+	pub fn position(mut self, position: [f32; 3]) -> Self {
+		self.position = position;
+		self
+	}
+
+	/// Sets the rotation as a quaternion (x, y, z, w), see [`axis_angle`]
+	pub fn rotation(mut self, rotation: [f32; 4]) -> Self {
+		self.rotation = rotation;
+		self
+	}
+
+	pub fn scale(mut self, scale: [f32; 3]) -> Self {
+		self.scale = scale;
+		self
+	}
+
+	/// Makes the shape's transform relative to that of the shape or group at index `parent`, see [`ShapeDesc::parent`]
+	pub fn parent(mut self, parent: usize) -> Self {
+		self.parent = Some(parent);
+		self
+	}
+
+	pub fn size(mut self, size: [f32; 3]) -> Self {
+		self.size = size;
+		self
+	}
+
+	/// Sets the size to `size` on every axis, e.g. the radius of a sphere
+	pub fn uniform_size(self, size: f32) -> Self {
+		self.size([size; 3])
+	}
+
+	pub fn albedo(mut self, albedo: [f32; 3]) -> Self {
+		self.albedo = albedo;
+		self
+	}
+
+	/// Colours the shape by `palette` rather than its albedo. Only fractal shapes can have palettes
+	pub fn palette(mut self, palette: PaletteDesc) -> Self {
+		self.palette = Some(palette);
+		self
+	}
+
+	/// Shades the shape with the material at `index` in the scene's material table, see [`ShapeDesc::material`]
+	pub fn material(mut self, index: usize) -> Self {
+		self.material = Some(index);
+		self
+	}
+
+	/// Sets the operations a [`ShapeKind::Kifs`] shape runs each iteration
+	pub fn ops(mut self, ops: Vec<FractalOpDesc>) -> Self {
+		self.ops = ops;
+		self
+	}
+
+	/// Sets the formulas a [`ShapeKind::Hybrid`] shape runs, see [`ShapeDesc::schedule`]
+	pub fn schedule(mut self, schedule: Vec<HybridStep>) -> Self {
+		self.schedule = schedule;
+		self
+	}
+
+	/// Repeats or mirrors the shape, see [`DomainOp`]
+	pub fn domain(mut self, domain: DomainOp) -> Self {
+		self.domain = Some(domain);
+		self
+	}
+
+	/// Adds a modifier to the end of the shape's modifier stack, see [`ModifierDesc`]
+	pub fn modifier(mut self, modifier: ModifierDesc) -> Self {
+		self.modifiers.push(modifier);
+		self
+	}
+
+	/// Sets the image a [`ShapeKind::Heightmap`] shape is raised from, see [`ShapeDesc::heightmap`]
+	pub fn heightmap<P: Into<PathBuf>>(mut self, path: P) -> Self {
+		self.heightmap = Some(path.into());
+		self
+	}
+
+	/// Sets the file a [`ShapeKind::Mesh`] shape is voxelized from, see [`ShapeDesc::mesh`]
+	pub fn mesh<P: Into<PathBuf>>(mut self, path: P) -> Self {
+		self.mesh = Some(path.into());
+		self
+	}
+
+	/// Sets the grid file a [`ShapeKind::Baked`] shape renders, see [`ShapeDesc::grid`]
+	pub fn grid<P: Into<PathBuf>>(mut self, path: P) -> Self {
+		self.grid = Some(path.into());
+		self
+	}
+
+	/// Sets how the shape is combined with the nodes before it. `strength` is the distance over which
+	/// [`BlendMode::Smooth`] merges shapes, and is ignored by the other modes
+	pub fn blend(mut self, mode: BlendMode, strength: f32) -> Self {
+		self.blend_mode = mode;
+		self.blend_strength = strength;
+		self
+	}
+
+	pub fn validate(&self) -> Result<(), ShapeError> {
+		if !self.position.iter().chain(&self.rotation).chain(&self.scale).chain(&self.size).chain(&self.albedo).chain([&self.blend_strength]).all(|v| v.is_finite()) {
+			return Err(ShapeError::NonFinite(self.kind));
+		}
+		if normalise_transform(self.rotation, self.scale).is_none() {
+			return Err(ShapeError::InvalidTransform(self.kind));
+		}
+		if self.blend_mode == BlendMode::Smooth && self.blend_strength <= 0. {
+			return Err(ShapeError::NonPositiveBlendStrength(self.kind, self.blend_strength));
+		}
+		if self.size.iter().any(|&s| s <= 0.) {
+			return Err(ShapeError::NonPositiveSize(self.kind, self.size));
+		}
+		if self.kind.needs_uniform_size() && (self.size[0] != self.size[1] || self.size[0] != self.size[2]) {
+			return Err(ShapeError::NonUniformSize(self.kind, self.size));
+		}
+
+		if let Some(palette) = &self.palette {
+			if !self.kind.is_fractal() {
+				return Err(ShapeError::InvalidParameter(self.kind, "only fractal shapes can be coloured by a palette"));
+			}
+			palette.validate().map_err(|e| ShapeError::Palette(self.kind, e))?;
+		}
+
+		if let ShapeKind::Kifs { .. } = self.kind {
+			if self.ops.is_empty() || self.ops.len() > MAX_FRACTAL_OPS {
+				return Err(ShapeError::OpCount(self.kind, self.ops.len()));
+			}
+			for (i, op) in self.ops.iter().enumerate() {
+				op.validate().map_err(|msg| ShapeError::InvalidOp(self.kind, i, msg))?;
+			}
+		} else if !self.ops.is_empty() {
+			return Err(ShapeError::InvalidParameter(self.kind, "only Kifs shapes can have ops"));
+		}
+
+		if let ShapeKind::Hybrid { .. } = self.kind {
+			for (i, step) in self.schedule.iter().enumerate() {
+				step.validate().map_err(|msg| ShapeError::InvalidStep(self.kind, i, msg))?;
+			}
+			// Each iteration in the schedule is laid out as its own op
+			let iterations = self.schedule.iter().map(|s| s.count as usize).sum::<usize>();
+			if iterations == 0 || iterations > MAX_FRACTAL_OPS {
+				return Err(ShapeError::ScheduleLength(self.kind, iterations));
+			}
+		} else if !self.schedule.is_empty() {
+			return Err(ShapeError::InvalidParameter(self.kind, "only Hybrid shapes can have a schedule"));
+		}
+
+		if self.kind == ShapeKind::Heightmap && self.heightmap.is_none() {
+			return Err(ShapeError::InvalidParameter(self.kind, "must have a heightmap"));
+		} else if self.kind != ShapeKind::Heightmap && self.heightmap.is_some() {
+			return Err(ShapeError::InvalidParameter(self.kind, "only Heightmap shapes can have a heightmap"));
+		}
+
+		let is_mesh = matches!(self.kind, ShapeKind::Mesh { .. });
+		if is_mesh && self.mesh.is_none() {
+			return Err(ShapeError::InvalidParameter(self.kind, "must have a mesh"));
+		} else if !is_mesh && self.mesh.is_some() {
+			return Err(ShapeError::InvalidParameter(self.kind, "only Mesh shapes can have a mesh"));
+		}
+
+		if self.kind == ShapeKind::Baked && self.grid.is_none() {
+			return Err(ShapeError::InvalidParameter(self.kind, "must have a grid"));
+		} else if self.kind != ShapeKind::Baked && self.grid.is_some() {
+			return Err(ShapeError::InvalidParameter(self.kind, "only Baked shapes can have a grid"));
+		}
+
+		if let Some(domain) = &self.domain {
+			domain.validate().map_err(|msg| ShapeError::InvalidDomain(self.kind, msg))?;
+		}
+		if self.modifiers.len() > MAX_MODIFIERS {
+			return Err(ShapeError::ModifierCount(self.kind, self.modifiers.len()));
+		}
+		for (i, modifier) in self.modifiers.iter().enumerate() {
+			modifier.validate().map_err(|msg| ShapeError::InvalidModifier(self.kind, i, msg))?;
+			if modifier.needs_bounded_shape() && self.bounding_radius().is_none() {
+				return Err(ShapeError::InvalidModifier(self.kind, i, "twists and bends can't be applied to unbounded shapes or shapes repeated forever".into()));
+			}
+		}
+
+		self.kind.validate_params(self.size)
+	}
+
+	/// Roughly how far the shape reaches from its centre, before scaling, or None if it carries on forever
+	pub fn bounding_radius(&self) -> Option<f32> {
+		if self.kind.is_unbounded() || matches!(self.domain, Some(DomainOp::Repeat { .. })) {
+			return None;
+		}
+		Some(self.size.iter().map(|s| s * s).sum::<f32>().sqrt() * self.kind.extent())
+	}
+
+	/// Validates the shape and lays it out as expected by the shader's shape buffer. The parent, palette and material indices,
+	/// and the ranges of any ops and modifiers, are left for the caller to fill in, as they depend on where the shape is in the scene
+	pub fn to_shape(&self) -> Result<Shape, ShapeError> {
+		self.validate()?;
+
+		let (domain_op, domain_params) = DomainOp::to_gpu(self.domain);
+
+		Ok(Shape {
+			position: self.position,
+			shape_type: self.kind.id(),
+			rotation: normalise_transform(self.rotation, self.scale).unwrap(),
+			scale: self.scale,
+			parent: -1,
+			size: self.size,
+			domain_op,
+			albedo: self.albedo,
+			palette: -1,
+			params: self.kind.params(),
+			domain_params,
+			first_modifier: 0,
+			num_modifiers: 0,
+			material: -1,
+			_dummy0: [0; 4],
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn valid_shape_passes() {
+		assert!(ShapeDesc::new(ShapeKind::Box).size([1., 2., 3.]).rotation([0., 0., 1., 1.]).validate().is_ok());
+	}
+
+	#[test]
+	fn shapes_that_need_uniform_sizes_are_rejected_without_one() {
+		assert!(ShapeDesc::new(ShapeKind::Sphere).uniform_size(2.).validate().is_ok());
+		assert!(matches!(
+			ShapeDesc::new(ShapeKind::Sphere).size([1., 2., 1.]).validate(),
+			Err(ShapeError::NonUniformSize(ShapeKind::Sphere, _))
+		));
+	}
+
+	#[test]
+	fn invalid_transforms_and_sizes_are_rejected() {
+		assert!(matches!(ShapeDesc::new(ShapeKind::Box).position([f32::NAN, 0., 0.]).validate(), Err(ShapeError::NonFinite(ShapeKind::Box))));
+		assert!(matches!(ShapeDesc::new(ShapeKind::Box).rotation([0.; 4]).validate(), Err(ShapeError::InvalidTransform(ShapeKind::Box))));
+		assert!(matches!(ShapeDesc::new(ShapeKind::Box).scale([1., 0., 1.]).validate(), Err(ShapeError::InvalidTransform(ShapeKind::Box))));
+		assert!(matches!(ShapeDesc::new(ShapeKind::Box).size([1., -1., 1.]).validate(), Err(ShapeError::NonPositiveSize(ShapeKind::Box, _))));
+	}
+
+	#[test]
+	fn invalid_parameters_are_rejected() {
+		let bulb = ShapeKind::Mandelbulb { power: 1., iterations: 8, bailout: 2. };
+		assert!(matches!(ShapeDesc::new(bulb).validate(), Err(ShapeError::InvalidParameter(_, _))));
+		let bulb = ShapeKind::Mandelbulb { power: 8., iterations: 0, bailout: 2. };
+		assert!(matches!(ShapeDesc::new(bulb).validate(), Err(ShapeError::IterationCount(_, 0))));
+	}
+
+	#[test]
+	fn limit_messages_give_the_limit() {
+		let bulb = ShapeKind::Mandelbulb { power: 8., iterations: MAX_FRACTAL_ITERATIONS + 1, bailout: 2. };
+		let msg = ShapeDesc::new(bulb).validate().unwrap_err().to_string();
+		assert!(msg.contains(&format!("between 1 and {} iterations", MAX_FRACTAL_ITERATIONS)), "{}", msg);
+	}
+
+	#[test]
+	fn twists_and_bends_need_bounded_shapes() {
+		let twist = ModifierDesc::Twist { rate: 1. };
+		assert!(ShapeDesc::new(ShapeKind::Box).modifier(twist).validate().is_ok());
+		assert!(matches!(
+			ShapeDesc::new(ShapeKind::Plane).modifier(ModifierDesc::Bend { rate: 1. }).validate(),
+			Err(ShapeError::InvalidModifier(ShapeKind::Plane, 0, _))
+		));
+		assert!(matches!(
+			ShapeDesc::new(ShapeKind::Box).domain(DomainOp::Repeat { spacing: [2., 0., 0.] }).modifier(twist).validate(),
+			Err(ShapeError::InvalidModifier(ShapeKind::Box, 0, _))
+		));
+		assert!(ShapeDesc::new(ShapeKind::Box).domain(DomainOp::RepeatLimited { spacing: [2., 0., 0.], limit: [1, 0, 0] }).modifier(twist).validate().is_ok());
+	}
+}
@@ -0,0 +1,126 @@
+use std::sync::Arc;
+
+use vulkano::{image::{ImmutableImage, ImageDimensions, MipmapsCount, view::ImageView}, format::Format, sampler::{Sampler, SamplerCreateInfo, SamplerAddressMode, Filter}, sync::GpuFuture};
+
+use crate::vulkan_computil::VkTarget;
+
+use super::{shaders::ray_marching_shader::ty::Shape, scene::SceneError, sdf_grid::VolumeSource, SHAPE_TYPE_MESH, SHAPE_TYPE_BAKED};
+
+/// Where a grid is in the atlas, and the size of its cells before the shapes sampling it are scaled
+#[derive(Debug, Clone, Copy)]
+struct Placement {
+	size: [u32; 3],
+	z_offset: u32,
+	cell_size: f32
+}
+
+/// The signed distance grids a scene's volume shapes sample, stacked along z in a single 3D image. Distances are
+/// stored in cells rather than scene units, so a grid can be scaled by scaling its cell size
+pub struct VolumeAtlas {
+	sources: Vec<VolumeSource>,
+	placements: Vec<Placement>,
+	view: Arc<ImageView<ImmutableImage>>,
+	sampler: Arc<Sampler>
+}
+
+impl VolumeAtlas {
+	/// Loads the grids from `sources` and uploads them, in order. With no sources a single point is uploaded, as
+	/// the shader always expects something bound
+	pub fn load(vk_target: &VkTarget, sources: &[VolumeSource]) -> Result<Self, SceneError> {
+		let grids = sources.iter().map(VolumeSource::load).collect::<Result<Vec<_>, _>>()?;
+
+		let width = grids.iter().map(|grid| grid.size()[0]).max().unwrap_or(1);
+		let height = grids.iter().map(|grid| grid.size()[1]).max().unwrap_or(1);
+		// Summed as u64, so many grids can't overflow before the size is checked
+		let depth = grids.iter().map(|grid| grid.size()[2] as u64).sum::<u64>().max(1);
+
+		let max_dimension = vk_target.device.physical_device().properties().max_image_dimension3_d;
+		if width.max(height) > max_dimension || depth > max_dimension as u64 {
+			return Err(SceneError::VolumesTooLarge(max_dimension));
+		}
+
+		let depth = depth as u32;
+
+		let mut distances = vec![0f32; width as usize * height as usize * depth as usize];
+		let mut placements = Vec::with_capacity(grids.len());
+		let mut z_offset = 0;
+		for grid in &grids {
+			let [size_x, size_y, size_z] = grid.size();
+			for z in 0..size_z {
+				for y in 0..size_y {
+					let src = (size_x * (y + size_y * z)) as usize;
+					let dst = width as usize * (y as usize + height as usize * (z + z_offset) as usize);
+					for (out, d) in distances[dst..dst + size_x as usize].iter_mut().zip(&grid.distances()[src..src + size_x as usize]) {
+						*out = d / grid.cell_size();
+					}
+				}
+			}
+
+			placements.push(Placement { size: grid.size(), z_offset, cell_size: grid.cell_size() });
+			z_offset += size_z;
+		}
+
+		// Sampled with texelFetch and interpolated in the shader, as linear filtering isn't guaranteed for float formats
+		let (image, future) = ImmutableImage::from_iter(
+			distances,
+			ImageDimensions::Dim3d { width, height, depth },
+			MipmapsCount::One,
+			Format::R32_SFLOAT,
+			vk_target.queue.clone()
+		).map_err(|e| SceneError::ImageCreation(Box::new(e)))?;
+		future.then_signal_fence_and_flush()
+			.and_then(|future| future.wait(None))
+			.map_err(|e| SceneError::ImageCreation(Box::new(e)))?;
+
+		let view = ImageView::new_default(image).unwrap();
+
+		let sampler = Sampler::new(vk_target.device.clone(), SamplerCreateInfo {
+			mag_filter: Filter::Nearest,
+			min_filter: Filter::Nearest,
+			address_mode: [SamplerAddressMode::ClampToEdge; 3],
+			..Default::default()
+		}).unwrap();
+
+		Ok(VolumeAtlas {
+			sources: sources.to_vec(),
+			placements,
+			view,
+			sampler
+		})
+	}
+
+	pub fn sources(&self) -> &[VolumeSource] {
+		&self.sources
+	}
+
+	pub fn view(&self) -> Arc<ImageView<ImmutableImage>> {
+		self.view.clone()
+	}
+
+	pub fn sampler(&self) -> Arc<Sampler> {
+		self.sampler.clone()
+	}
+
+	/// Fills in where the volume shapes' grids are in the atlas, and where their first points are and how far apart
+	/// they are once scaled by the shape's size. Mesh shapes are scaled so the longest side of the grid spans twice
+	/// the size, and baked shapes by the size. Each shape's Shape::params[0].x is the index of its grid, which this overwrites
+	pub fn set_volume_params(&self, shapes: &mut [Shape]) {
+		for shape in shapes.iter_mut().filter(|shape| shape.shape_type == SHAPE_TYPE_MESH || shape.shape_type == SHAPE_TYPE_BAKED) {
+			let placement = self.placements[shape.params[0][0] as usize];
+
+			let extents = placement.size.map(|s| (s - 1) as f32 * placement.cell_size);
+			let scale = if shape.shape_type == SHAPE_TYPE_MESH {
+				2. * shape.size[0] / extents.iter().fold(0f32, |a, &b| a.max(b))
+			} else {
+				shape.size[0]
+			};
+
+			// Laid out as (first point, cell size), (points along each axis, z offset in the atlas), centred on the shape
+			let [size_x, size_y, size_z] = placement.size;
+			shape.params = [
+				[-extents[0] * scale / 2., -extents[1] * scale / 2., -extents[2] * scale / 2., placement.cell_size * scale],
+				[size_x as f32, size_y as f32, size_z as f32, placement.z_offset as f32]
+			];
+		}
+	}
+}
@@ -2,4 +2,6 @@ use druid::Data;
 
 #[derive(Debug, Clone, Data)]
 pub struct RendererData {
+	/// The error from the last failed scene reload, if the scene hasn't since been reloaded successfully
+	pub scene_error: Option<String>
 }
\ No newline at end of file
@@ -8,22 +8,40 @@ mod minifb_renderer;
 
 // TODO List
 // Camera control
-// Shadows (do in shader ofc)
 // Ability to control more of the rendering through the SceneInfo struct
-// Also flesh out the mandelbulb rendering as different to a normal shape - Or at least, have the ability to pass out the iterations it took for colouring purposes
 // UI to control rendering (do in something like imgui or just have another window running druid. Could actually display the fractal in the window running druid perhaps)
 // Faster fractal rendering using vulkan fragment shaders or something (or if druid does it fast enough... I don't suppose it will though)
 
+use std::{env, path::PathBuf, ffi::OsString, process};
+
+use compute::{Raymarch, GridRegion};
 use data::RendererData;
 use delegate::Delegate;
 use druid::{AppLauncher, WindowDesc, PlatformError};
 use view::build_ui;
 
+const EXPORT_USAGE: &str = "Usage: vulkano_raymarching [scene file] --export <mesh file (.obj or .stl)> <min x,y,z> <max x,y,z> [resolution]
+   or: vulkano_raymarching [scene file] --bake <grid file> <min x,y,z> <max x,y,z> [resolution]";
+
+/// The resolution meshes are exported and grids baked at unless another is given
+const DEFAULT_EXPORT_RESOLUTION: u32 = 128;
+
+// Usage: vulkano_raymarching [scene file]
+// Or to save the scene's surface as a mesh, or its distances as a grid file, rather than showing it, see EXPORT_USAGE
 fn main() -> Result<(), PlatformError> {
-	minifb_renderer::mkminifb();
-	let data = RendererData {};
+	let args: Vec<OsString> = env::args_os().skip(1).collect();
+	let export_at = args.iter().position(|arg| arg == "--export" || arg == "--bake");
+	let scene_path = args.first().filter(|_| export_at != Some(0)).map(PathBuf::from);
 
-	AppLauncher::with_window(WindowDesc::new(build_ui())
+	if let Some(i) = export_at {
+		export(scene_path, args[i] == "--bake", &args[i + 1..]);
+		return Ok(());
+	}
+
+	minifb_renderer::mkminifb(scene_path.clone());
+	let data = RendererData { scene_error: None };
+
+	AppLauncher::with_window(WindowDesc::new(build_ui(scene_path))
 		.title("Raymarching Renderer")
 		.window_size((1024., 800.))
 	)
@@ -32,3 +50,47 @@ fn main() -> Result<(), PlatformError> {
 
 	Ok(())
 }
+
+/// Sets up the renderer with the scene at `scene_path`, or the default scene if there isn't one, exiting if the
+/// scene fails to load
+fn load_raymarch(scene_path: Option<PathBuf>) -> Raymarch {
+	match scene_path {
+		Some(scene_path) => Raymarch::from_scene(scene_path).unwrap_or_else(|e| {
+			eprintln!("{}", e);
+			process::exit(1);
+		}),
+		None => Raymarch::new()
+	}
+}
+
+/// Saves the surface of the scene within the box given by `args` as a mesh, or its distances as a grid file if `bake`
+/// is set, exiting if it fails
+fn export(scene_path: Option<PathBuf>, bake: bool, args: &[OsString]) {
+	let parse_point = |arg: Option<&OsString>| -> Option<[f32; 3]> {
+		let values = arg?.to_str()?.split(',').map(|v| v.trim().parse().ok()).collect::<Option<Vec<f32>>>()?;
+		values.try_into().ok()
+	};
+	let resolution = match args.get(3) {
+		Some(arg) => arg.to_str().and_then(|r| r.parse().ok()),
+		None => Some(DEFAULT_EXPORT_RESOLUTION)
+	};
+
+	let (path, region) = match (args.first(), parse_point(args.get(1)), parse_point(args.get(2)), resolution) {
+		(Some(path), Some(min), Some(max), Some(resolution)) => (PathBuf::from(path), GridRegion::new(min, max, resolution)),
+		_ => {
+			eprintln!("{}", EXPORT_USAGE);
+			process::exit(1);
+		}
+	};
+
+	let raymarch = load_raymarch(scene_path);
+
+	let result = if bake { raymarch.bake(&path, &region) } else { raymarch.export_mesh(&path, &region) };
+	match result {
+		Ok(()) => println!("Exported the scene to {}", path.display()),
+		Err(e) => {
+			eprintln!("{}", e);
+			process::exit(1);
+		}
+	}
+}
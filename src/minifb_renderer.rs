@@ -1,8 +1,8 @@
 use glm::{Vector3, vec3, cross, normalize, vec2, Vector2};
-use minifb::{Window, WindowOptions, Key, MouseButton, MouseMode};
-use std::{time::Instant, f32::consts::PI};
+use minifb::{Window, WindowOptions, Key, KeyRepeat, MouseButton, MouseMode};
+use std::{time::Instant, f32::consts::PI, path::PathBuf};
 
-use crate::compute::Raymarch;
+use crate::compute::OutputMode;
 
 fn from_arr(a: &[f32; 3]) -> Vector3<f32> {
 	vec3(a[0], a[1], a[2])
@@ -76,11 +76,19 @@ fn to_camera_space(p: Vector2<f32>, cam_pos: Vector3<f32>, look_at: Vector3<f32>
 	(p3d.x, p3d.y, p3d.z)
 }
 
-pub fn mkminifb() {
-	let raymarch = Raymarch::new();
+fn window_title(fps: Option<f32>, scene_error: Option<&str>) -> String {
+	let fps = fps.map_or("-".to_string(), |fps| fps.to_string());
+	match scene_error {
+		Some(e) => format!("Raymarching - ESC To Exit ({} fps) - {}", fps, e),
+		None => format!("Raymarching - ESC To Exit ({} fps)", fps)
+	}
+}
+
+pub fn mkminifb(scene_path: Option<PathBuf>) {
+	let mut raymarch = crate::load_raymarch(scene_path);
 
 	let mut window = Window::new(
-		"Raymarching - ESC To Exit (- fps)",
+		&window_title(None, None),
 		1024 as usize,
 		1024 as usize,
 		WindowOptions::default()
@@ -92,6 +100,10 @@ pub fn mkminifb() {
 	let mut delta_time = Instant::now();
 
 	let mut frame_count = 0;
+	let mut fps: Option<f32> = None;
+
+	// The error from the last failed scene reload, shown in the title until the scene loads successfully
+	let mut scene_error: Option<String> = None;
 
 	const MOVE_AMT: f32 = 0.1;
 
@@ -103,6 +115,17 @@ pub fn mkminifb() {
 	let mut speed_phi: f32 = 0.;
 
 	while window.is_open() && !window.is_key_down(Key::Escape) {
+		if let Some(result) = raymarch.reload_scene_if_changed() {
+			scene_error = match result {
+				Ok(()) => None,
+				Err(e) => {
+					eprintln!("{}", e);
+					Some(e.to_string())
+				}
+			};
+			window.set_title(&window_title(fps, scene_error.as_deref()));
+		}
+
 		// if window.is_key_down(Key::Up) || window.is_key_down(Key::Down) {
 		// 	let dir: f32 = if window.is_key_down(Key::Up) { 1. } else { -1. };
 		// 	let mut write_handle = raymarch._info_buffer.write().unwrap();
@@ -152,6 +175,20 @@ pub fn mkminifb() {
 			write_handle.look_at = from_vec(tar_pos);
 		}
 
+		// Shadows are the most expensive part of shading, so they can be turned off to move around faster
+		if window.is_key_pressed(Key::H, KeyRepeat::No) {
+			let enabled = !raymarch.shadows();
+			raymarch.set_shadows(enabled);
+		}
+
+		if window.is_key_pressed(Key::O, KeyRepeat::No) {
+			let output_mode = match raymarch.output_mode() {
+				OutputMode::Shaded => OutputMode::AmbientOcclusion,
+				OutputMode::AmbientOcclusion => OutputMode::Shaded
+			};
+			raymarch.set_output_mode(output_mode);
+		}
+
 		if window.is_key_down(Key::Space) || window.is_key_down(Key::LeftShift) {
 			let dir: f32 = if window.is_key_down(Key::Space) { 1. } else { -1. };
 			let mut write_handle = raymarch._info_buffer.write().unwrap();
@@ -226,7 +263,8 @@ pub fn mkminifb() {
 		frame_count += 1;
 
 		if frame_count == 10 {
-			window.set_title(&format!("Raymarching - ESC To Exit ({} fps)", frame_count as f32 / delta_time.elapsed().as_secs_f32()));
+			fps = Some(frame_count as f32 / delta_time.elapsed().as_secs_f32());
+			window.set_title(&window_title(fps, scene_error.as_deref()));
 			delta_time = Instant::now();
 			frame_count = 0;
 		}
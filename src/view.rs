@@ -1,19 +1,21 @@
 mod renderer_widget;
 
-use druid::{Widget, widget::{Flex, MainAxisAlignment, Label}, WidgetExt};
+use std::path::PathBuf;
+
+use druid::{Widget, widget::{Flex, MainAxisAlignment, Label, LineBreaking}, WidgetExt};
 
 use crate::data::RendererData;
 
 use self::renderer_widget::RendererWidget;
 
-pub fn build_ui() -> impl Widget<RendererData> {
+pub fn build_ui(scene_path: Option<PathBuf>) -> impl Widget<RendererData> {
 	Flex::row()
 		.with_flex_child(
 			Flex::column()
 				.with_default_spacer()
 				.with_child(Label::new("Renderer Window").with_text_size(32.))
 				.with_flex_spacer(0.5)
-				.with_child(RendererWidget::new())
+				.with_child(RendererWidget::new(scene_path))
 				.with_flex_spacer(0.5)
 				.expand_width(),
 			1.0
@@ -23,6 +25,11 @@ pub fn build_ui() -> impl Widget<RendererData> {
 			Flex::column()
 				.with_default_spacer()
 				.with_child(Label::new("Control Panel").with_text_size(32.))
+				.with_default_spacer()
+				.with_child(Label::dynamic(|data: &RendererData, _| data.scene_error.clone().unwrap_or_default())
+					.with_line_break_mode(LineBreaking::WordWrap)
+					.fix_width(300.)
+				)
 				.with_flex_spacer(1.0)
 				.with_default_spacer()
 		)
@@ -1,24 +1,53 @@
-use druid::{Widget, Size, RenderContext, piet::{ImageFormat, InterpolationMode}, Data};
+use std::{path::PathBuf, time::Duration};
+
+use druid::{Widget, Size, RenderContext, piet::{ImageFormat, InterpolationMode}, Data, TimerToken, Event, LifeCycle};
 
 use crate::{compute::{Raymarch, self}, data::RendererData};
 
+/// How often to check the scene file for changes
+const SCENE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
 pub struct RendererWidget {
 	engine: Raymarch,
-	img_data: Option<Vec<u8>>
+	img_data: Option<Vec<u8>>,
+	reload_timer: TimerToken
 }
 
 impl RendererWidget {
-	pub fn new() -> Self {
-		RendererWidget { engine: Raymarch::new(), img_data: None }
+	pub fn new(scene_path: Option<PathBuf>) -> Self {
+		let engine = crate::load_raymarch(scene_path);
+
+		RendererWidget { engine, img_data: None, reload_timer: TimerToken::INVALID }
 	}
 }
 
 #[allow(unused)]
 impl Widget<RendererData> for RendererWidget {
     fn event(&mut self, ctx: &mut druid::EventCtx, event: &druid::Event, data: &mut RendererData, env: &druid::Env) {
+		if let Event::Timer(token) = event {
+			if *token == self.reload_timer {
+				if let Some(result) = self.engine.reload_scene_if_changed() {
+					match result {
+						Ok(()) => {
+							data.scene_error = None;
+							self.img_data.take();
+							ctx.request_paint();
+						},
+						Err(e) => {
+							eprintln!("{}", e);
+							data.scene_error = Some(e.to_string());
+						}
+					}
+				}
+				self.reload_timer = ctx.request_timer(SCENE_POLL_INTERVAL);
+			}
+		}
     }
 
     fn lifecycle(&mut self, ctx: &mut druid::LifeCycleCtx, event: &druid::LifeCycle, data: &RendererData, env: &druid::Env) {
+		if let LifeCycle::WidgetAdded = event {
+			self.reload_timer = ctx.request_timer(SCENE_POLL_INTERVAL);
+		}
     }
 
     fn update(&mut self, ctx: &mut druid::UpdateCtx, old_data: &RendererData, data: &RendererData, env: &druid::Env) {
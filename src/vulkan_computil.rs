@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use vulkano::{instance::{Instance, InstanceExtensions, InstanceCreateInfo}, device::{physical::{PhysicalDevice, PhysicalDeviceType}, Device, Queue, DeviceExtensions, DeviceCreateInfo, QueueCreateInfo, QueueFamilyProperties, QueueFlags}, VulkanLibrary, sync::PipelineStage, swapchain::Surface};
+use vulkano::{buffer::{CpuAccessibleBuffer, BufferUsage, BufferContents}, instance::{Instance, InstanceExtensions, InstanceCreateInfo}, device::{physical::{PhysicalDevice, PhysicalDeviceType}, Device, Queue, DeviceExtensions, DeviceCreateInfo, QueueCreateInfo, QueueFamilyProperties, QueueFlags}, VulkanLibrary, sync::PipelineStage, swapchain::Surface};
 
 pub const VK_QUEUEFLAGS_COMPUTE: QueueFlags = QueueFlags { compute: true, ..QueueFlags::empty() };
 
@@ -18,6 +18,14 @@ pub struct VkTarget {
 pub struct VkComputePipeline { // TODO: Write a VkComputePipeline class
 }
 
+/// A Vec mirrored into a storage buffer. The buffer is reallocated with more room when the Vec outgrows it
+pub struct VkStorageVec<T> where [T]: BufferContents {
+	device: Arc<Device>,
+	data: Vec<T>,
+	buffer: Arc<CpuAccessibleBuffer<[T]>>,
+	capacity: usize
+}
+
 impl VkInstance {
 	pub fn new(ext: Option<InstanceExtensions>) -> Self {
 		let library = VulkanLibrary::new().unwrap();
@@ -85,3 +93,61 @@ impl VkTarget {
 			}).expect("No vulkan implementations found")
 	}
 }
+
+
+impl<T: Copy + Default> VkStorageVec<T> where [T]: BufferContents {
+	pub fn new(device: Arc<Device>, data: Vec<T>) -> Self {
+		let capacity = Self::capacity_for(data.len());
+		let buffer = Self::create_buffer(device.clone(), &data, capacity);
+
+		VkStorageVec {
+			device,
+			data,
+			buffer,
+			capacity
+		}
+	}
+
+	pub fn buffer(&self) -> Arc<CpuAccessibleBuffer<[T]>> {
+		self.buffer.clone()
+	}
+
+	pub fn len(&self) -> usize {
+		self.data.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.data.is_empty()
+	}
+
+	pub fn set(&mut self, data: Vec<T>) {
+		self.data = data;
+	}
+
+	/// Copies the data into the buffer. Returns true if the buffer had to be reallocated, in which case any
+	/// descriptor sets referring to the old buffer need recreating
+	pub fn upload(&mut self) -> bool {
+		if self.data.len() > self.capacity {
+			self.capacity = Self::capacity_for(self.data.len());
+			self.buffer = Self::create_buffer(self.device.clone(), &self.data, self.capacity);
+			true
+		} else {
+			self.buffer.write().unwrap()[..self.data.len()].copy_from_slice(&self.data);
+			false
+		}
+	}
+
+	// Buffers can't have a size of zero, so there is always room for at least one element
+	fn capacity_for(len: usize) -> usize {
+		len.max(1).next_power_of_two()
+	}
+
+	fn create_buffer(device: Arc<Device>, data: &[T], capacity: usize) -> Arc<CpuAccessibleBuffer<[T]>> {
+		CpuAccessibleBuffer::from_iter(
+			device,
+			BufferUsage { storage_buffer: true, ..Default::default() },
+			false,
+			(0..capacity).map(|i| data.get(i).copied().unwrap_or_default())
+		).expect("Failed to create buffer")
+	}
+}
\ No newline at end of file